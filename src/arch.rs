@@ -9,6 +9,7 @@ use std::{
     process::exit,
 };
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(ValueEnum, Clone, Copy, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
 pub(crate) enum Architecture {
     X86_64,
@@ -41,7 +42,7 @@ impl From<Architecture> for String {
 
 impl Display for Architecture {
     fn fmt(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(formatter, "{}", String::from(self).replace("_", "-"))
+        write!(formatter, "{}", String::from(self).replace('_', "-"))
     }
 }
 
@@ -79,4 +80,16 @@ impl Architecture {
             Architecture::RISCV64 => "BOOTRISCV64.EFI"
         })
     }
+
+    /// The architecture name used by `set architecture` in GDB
+    pub(crate) fn gdb_architecture(&self) -> &'static str {
+        match self {
+            Architecture::X86_64 => "i386:x86-64",
+            Architecture::X86 => "i386",
+            Architecture::ARM => "arm",
+            Architecture::ARM64 => "aarch64",
+            Architecture::RISCV32 => "riscv:rv32",
+            Architecture::RISCV64 => "riscv:rv64"
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,230 @@
+use std::collections::BTreeMap;
+use cargo_toml::Manifest;
+use serde::Deserialize;
+use toml::Value;
+use crate::error::Error;
+use crate::image::FileSystemKind;
+use crate::image::fat::MIN_FAT32_SECTORS;
+use crate::image::gpt::parse_guid;
+use crate::tasks::qemu::devices::Network;
+use crate::tasks::qemu::firmware::Firmware;
+use crate::tasks::runner::RunnerKind;
+use crate::utils::parse_size;
+
+/// Configuration of QEMU, read from the `qemu` section of the OSImage metadata
+#[derive(Deserialize, Clone, Default, Debug)]
+#[serde(default, rename_all = "kebab-case")]
+pub(crate) struct QemuConfig {
+    pub(crate) memory: Option<String>,
+    pub(crate) smp: Option<u32>,
+    pub(crate) machine: Option<String>,
+    pub(crate) cpu: Option<String>,
+    pub(crate) kvm: Option<bool>,
+    pub(crate) firmware: Option<Firmware>,
+    pub(crate) firmware_path: Option<String>,
+    pub(crate) net: Option<Network>,
+    pub(crate) nic_model: Option<String>,
+    pub(crate) tap_interface: Option<String>,
+    pub(crate) disks: Vec<String>,
+    pub(crate) usb: Option<bool>,
+    pub(crate) devices: Vec<String>
+}
+
+/// Configuration of the kernel test runner, read from the `test` section of the OSImage metadata
+#[derive(Deserialize, Clone, Default, Debug)]
+#[serde(default, rename_all = "kebab-case")]
+pub(crate) struct TestConfig {
+    pub(crate) timeout: Option<u64>,
+    pub(crate) success_exit_code: Option<i32>,
+    pub(crate) junit: Option<String>,
+    pub(crate) tap: Option<String>
+}
+
+/// Configuration of the runner, read from the `runner` section of the OSImage metadata
+#[derive(Deserialize, Clone, Default, Debug)]
+#[serde(default, rename_all = "kebab-case")]
+pub(crate) struct RunnerConfig {
+    pub(crate) backend: Option<RunnerKind>,
+    pub(crate) command: Option<String>
+}
+
+/// Attributes, timestamps and names of a file or directory in the FAT image. The path is the path of the entry in the
+/// image (e.g. `EFI/BOOT/KERNEL.ELF`). Timestamps are specified as `YYYY-MM-DD HH:MM:SS`.
+#[derive(Deserialize, Clone, Default, Debug)]
+#[serde(default, rename_all = "kebab-case")]
+pub(crate) struct LayoutEntry {
+    pub(crate) path: String,
+    pub(crate) read_only: Option<bool>,
+    pub(crate) hidden: Option<bool>,
+    pub(crate) system: Option<bool>,
+    pub(crate) created: Option<String>,
+    pub(crate) modified: Option<String>,
+    pub(crate) accessed: Option<String>,
+    /// The 8.3 short name of the entry (e.g. `KERNEL.ELF`), replaces the short name generated from the long name
+    pub(crate) short_name: Option<String>,
+    /// Keep the long file name of the entry. Without long name, the entry is only visible with the short name.
+    pub(crate) long_name: Option<bool>
+}
+
+/// Configuration of the image layout, read from the `layout` section of the OSImage metadata
+#[derive(Deserialize, Clone, Default, Debug)]
+#[serde(default, rename_all = "kebab-case")]
+pub(crate) struct LayoutConfig {
+    pub(crate) entries: Vec<LayoutEntry>
+}
+
+/// Identification of the FAT volume and the ISO file, read from the `volume` section of the OSImage metadata
+#[derive(Deserialize, Clone, Default, Debug)]
+#[serde(default, rename_all = "kebab-case")]
+pub(crate) struct VolumeConfig {
+    /// The label of the FAT volume (up to 11 characters)
+    pub(crate) label: Option<String>,
+    /// The serial number of the FAT volume
+    pub(crate) id: Option<u32>,
+    /// The OEM name in the boot sector of the FAT volume (up to 8 characters)
+    pub(crate) oem_name: Option<String>,
+    /// The volume ID of the ISO file (up to 32 characters, default: EFI_ISO_BOOT)
+    pub(crate) iso_volume_id: Option<String>,
+    pub(crate) iso_publisher: Option<String>,
+    pub(crate) iso_application: Option<String>
+}
+
+impl VolumeConfig {
+    /// Validate the length and characters of the names, because they're stored in fixed-size fields
+    fn validate(&self) -> Result<(), Error> {
+        let fields = [("label", self.label.as_ref(), 11), ("oem-name", self.oem_name.as_ref(), 8),
+            ("iso-volume-id", self.iso_volume_id.as_ref(), 32), ("iso-publisher", self.iso_publisher.as_ref(), 128),
+            ("iso-application", self.iso_application.as_ref(), 128)];
+        for (name, value, length) in fields {
+            let Some(value) = value else {
+                continue;
+            };
+
+            let valid_characters = value.chars().all(|character| character.is_ascii() && !character.is_ascii_control());
+            if value.len() > length || !valid_characters {
+                return Err(Error::InvalidConfig(format!("Volume {} '{}' must be up to {} ASCII characters", name, value,
+                    length)));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An additional partition of the raw image, read from the `partitions` array of the OSImage metadata. The partitions
+/// follow the EFI system partition in the GUID partition table. The files of the directory (relative to the workspace)
+/// and the listed files (path in the partition to host path) are written into the file system of the partition.
+#[derive(Deserialize, Clone, Default, Debug)]
+#[serde(default, rename_all = "kebab-case")]
+pub(crate) struct PartitionConfig {
+    pub(crate) name: String,
+    pub(crate) filesystem: FileSystemKind,
+    /// The size of the partition (e.g. `64M`)
+    pub(crate) size: String,
+    /// The partition type GUID, the default type depends on the file system
+    pub(crate) type_guid: Option<String>,
+    pub(crate) label: Option<String>,
+    pub(crate) directory: Option<String>,
+    pub(crate) files: BTreeMap<String, String>,
+    /// The command template generating the custom file system
+    pub(crate) command: Option<String>
+}
+
+impl PartitionConfig {
+    fn validate(&self) -> Result<(), Error> {
+        let invalid = |message: String| Err(Error::InvalidConfig(format!("Partition '{}' {}", self.name, message)));
+        if self.name.is_empty() || self.name.contains(['/', '\\']) {
+            return invalid(String::from("must have a name without path separators"));
+        }
+
+        let size = match parse_size(&self.size) {
+            Ok(size) => size,
+            Err(error) => return invalid(format!("has no valid size => {}", error))
+        };
+
+        // FAT partitions are formatted as FAT32 with 512-byte sectors
+        let min_fat_size = MIN_FAT32_SECTORS as u64 * 512;
+        if self.filesystem == FileSystemKind::Fat && size < min_fat_size {
+            return invalid(format!("is too small for FAT32 (at least {}M)", min_fat_size.div_ceil(1024 * 1024)));
+        }
+
+        if self.type_guid.as_deref().is_some_and(|guid| parse_guid(guid).is_none()) {
+            return invalid(format!("has an invalid type GUID '{}'", self.type_guid.as_ref().unwrap()));
+        }
+
+        let label_length = match self.filesystem {
+            FileSystemKind::Fat => 11,
+            FileSystemKind::Ext2 | FileSystemKind::Custom => 16
+        };
+        if self.label.as_ref().is_some_and(|label| label.len() > label_length || !label.is_ascii()) {
+            return invalid(format!("must have a label with up to {} ASCII characters", label_length));
+        }
+
+        if self.filesystem == FileSystemKind::Custom && self.command.is_none() {
+            return invalid(String::from("has a custom file system without command"));
+        }
+        Ok(())
+    }
+}
+
+/// Configuration of the built artifacts, read from the `artifact` section of the OSImage metadata
+#[derive(Deserialize, Clone, Default, Debug)]
+#[serde(default, rename_all = "kebab-case")]
+pub(crate) struct ArtifactConfig {
+    /// The directory (relative to the workspace) in which the artifacts are placed
+    pub(crate) out_dir: Option<String>
+}
+
+/// Configuration of OSImage, read from `[workspace.metadata.osimage]` and `[package.metadata.osimage]` of the root
+/// manifest. Values of the package section override values of the workspace section.
+#[derive(Deserialize, Clone, Default, Debug)]
+#[serde(default, rename_all = "kebab-case")]
+pub(crate) struct Config {
+    pub(crate) qemu: QemuConfig,
+    pub(crate) test: TestConfig,
+    pub(crate) runner: RunnerConfig,
+    pub(crate) layout: LayoutConfig,
+    pub(crate) volume: VolumeConfig,
+    pub(crate) partitions: Vec<PartitionConfig>,
+    pub(crate) artifact: ArtifactConfig
+}
+
+/// Merge the overlay value into the base value. Tables are merged recursively, all other values are replaced.
+fn merge_values(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Table(base), Value::Table(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(base_value) => merge_values(base_value, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay
+    }
+}
+
+impl Config {
+    pub(crate) fn from_manifest(manifest: &Manifest<Value>) -> Result<Self, Error> {
+        let mut value = Value::Table(Default::default());
+        let workspace_metadata = manifest.workspace.as_ref().and_then(|workspace| workspace.metadata.as_ref());
+        let package_metadata = manifest.package.as_ref().and_then(|package| package.metadata.as_ref());
+        for metadata in [workspace_metadata, package_metadata].into_iter().flatten() {
+            if let Some(osimage_data) = metadata.get("osimage") {
+                merge_values(&mut value, osimage_data.clone());
+            }
+        }
+
+        let config: Config = value.try_into()
+            .map_err(|error: toml::de::Error| Error::InvalidConfig(error.message().to_owned()))?;
+        config.volume.validate()?;
+        for (index, partition) in config.partitions.iter().enumerate() {
+            partition.validate()?;
+            if config.partitions[..index].iter().any(|other| other.name == partition.name) {
+                return Err(Error::InvalidConfig(format!("Partition '{}' is defined multiple times", partition.name)));
+            }
+        }
+        Ok(config)
+    }
+}
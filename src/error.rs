@@ -4,7 +4,11 @@ use thiserror::Error;
 pub(crate) const EXIT_INVALID_WORKSPACE: i32 = -1;
 pub(crate) const EXIT_BUILD_ERROR: i32 = -2;
 pub(crate) const EXIT_QEMU_ERROR: i32 = -3;
+pub(crate) const EXIT_KEY_GENERATION_ERROR: i32 = -4;
+pub(crate) const EXIT_INVALID_CONFIG: i32 = -5;
+pub(crate) const EXIT_TEST_ERROR: i32 = -6;
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("No Cargo.toml file found in '{0}'")]
@@ -25,4 +29,44 @@ pub enum Error {
     InvalidParameter(String),
     #[error("{0} failed with error code {1}")]
     ProcessFailed(String, i32),
+    #[error("File '{0}' not found")]
+    FileNotFound(String),
+    #[error("'{0}' is not a valid executable")]
+    InvalidExecutable(String),
+    #[error("Invalid OSImage configuration => {0}")]
+    InvalidConfig(String),
+    #[error("Unable to locate {0} firmware, specify the firmware path or set OSIMAGE_FIRMWARE_PATH")]
+    FirmwareNotFound(String),
+    #[error("{0} of {1} test binaries failed")]
+    TestsFailed(usize, usize),
+    #[error("{0}")]
+    RegexError(#[from] regex::Error),
+    #[error("Failure pattern matched by serial output '{0}'")]
+    FailurePatternMatched(String),
+    #[error("Expected pattern didn't appear in serial output")]
+    ExpectedPatternMissing,
+    #[error("Timeout of {0} seconds elapsed")]
+    Timeout(u64),
+    #[error("{0}")]
+    JsonError(#[from] serde_json::Error),
+    #[error("QMP error => {0}")]
+    QmpError(String),
+    #[error("Unable to read debug information of '{0}' => {1}")]
+    SymbolizerError(String, String),
+    #[error("{0} doesn't support {1}")]
+    UnsupportedRunner(String, String),
+    #[error("Kernel '{0}' has no valid {1} header => {2}")]
+    InvalidBootProtocol(String, String, String),
+    #[error("Unable to apply layout to '{0}' => {1}")]
+    LayoutError(String, String),
+    #[error("Unable to write partition '{0}' => {1}")]
+    PartitionError(String, String),
+    #[error("Refusing to overwrite '{0}' => {1}")]
+    OverwriteRefused(String, String),
+    #[error("Image file '{0}' has {1} bytes, but the volume requires {2} bytes")]
+    ImageTooSmall(String, u64, u64),
+    #[error("FAT32 volume with {0} bytes is too small, FAT32 requires at least {1} bytes")]
+    FatVolumeTooSmall(u64, u64),
+    #[error("QEMU instance of the workspace is already running, the QMP socket '{0}' is in use")]
+    InstanceRunning(String),
 }
\ No newline at end of file
@@ -0,0 +1,130 @@
+use std::fs::{copy, create_dir_all, remove_dir_all, remove_file, File, OpenOptions};
+use std::io;
+use std::io::{BufReader, Seek, SeekFrom, Write};
+use std::path;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use colorful::{Color, Colorful};
+use log::debug;
+use crate::config::PartitionConfig;
+use crate::error::Error;
+use crate::image::{COPY_BUFFER_SIZE, FileSystemWriter};
+use crate::utils::state_directory;
+
+/// Writer of a file system generated by a custom command. The files are staged in the directory
+/// `target/osimage/partitions/<name>` and the command template is run when the writer is finished. The template is
+/// split at whitespaces and the placeholders `{directory}`, `{output}`, `{size}` (in bytes), `{label}` and `{name}`
+/// are replaced in each argument. The output file of the command is copied into the partition and the staged files
+/// are removed, unless the intermediate files are kept.
+pub(crate) struct CustomWriter {
+    image: PathBuf,
+    offset: u64,
+    size: u64,
+    workspace: PathBuf,
+    directory: PathBuf,
+    output: PathBuf,
+    partition: PartitionConfig,
+    keep_intermediate: bool
+}
+
+impl CustomWriter {
+    pub(crate) fn new<W: AsRef<Path>, I: AsRef<Path>>(workspace: W, image: I, offset: u64, size: u64,
+                                                      partition: &PartitionConfig,
+                                                      keep_intermediate: bool) -> Result<Self, Error> {
+        // The staging directory and the output are absolute, because the command runs in the workspace
+        let partitions = path::absolute(state_directory(&workspace)?.join("partitions"))?;
+        let directory = partitions.join(&partition.name);
+        if directory.exists() {
+            remove_dir_all(&directory)?;
+        }
+        create_dir_all(&directory)?;
+
+        let output = partitions.join(format!("{}.img", partition.name));
+        if output.exists() {
+            remove_file(&output)?;
+        }
+
+        Ok(CustomWriter {
+            image: image.as_ref().to_path_buf(),
+            offset,
+            size,
+            workspace: workspace.as_ref().to_path_buf(),
+            directory,
+            output,
+            partition: partition.clone(),
+            keep_intermediate
+        })
+    }
+
+    fn error(&self, message: String) -> Error {
+        Error::PartitionError(self.partition.name.clone(), message)
+    }
+}
+
+impl FileSystemWriter for CustomWriter {
+    fn name(&self) -> &'static str {
+        "custom"
+    }
+
+    fn create_directory(&mut self, directory: &Path) -> Result<(), Error> {
+        create_dir_all(self.directory.join(directory))?;
+        Ok(())
+    }
+
+    fn copy_into(&mut self, host_file: &Path, image_file: &Path) -> Result<(), Error> {
+        let target_file = self.directory.join(image_file);
+        debug!("Stage {} as {}", host_file.to_str().unwrap().gradient(Color::Cyan),
+            target_file.to_str().unwrap().gradient(Color::Red));
+        create_dir_all(target_file.parent().unwrap())?;
+        copy(host_file, target_file)?;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), Error> {
+        let template = self.partition.command.as_ref()
+            .ok_or(self.error(String::from("No command for the custom file system specified")))?;
+        let placeholders = [
+            ("{directory}", self.directory.to_str().unwrap().to_owned()),
+            ("{output}", self.output.to_str().unwrap().to_owned()),
+            ("{size}", self.size.to_string()),
+            ("{label}", self.partition.label.clone().unwrap_or_else(|| self.partition.name.clone())),
+            ("{name}", self.partition.name.clone())
+        ];
+
+        let mut arguments = template.split_whitespace().map(|argument| {
+            placeholders.iter().fold(argument.to_owned(), |argument, (placeholder, value)| {
+                argument.replace(placeholder, value)
+            })
+        });
+        let program = arguments.next().ok_or(self.error(String::from("The command is empty")))?;
+        debug!("Run custom file system command {}", program);
+
+        let exit_status = Command::new(&program).args(arguments).current_dir(&self.workspace).status()?;
+        if !exit_status.success() {
+            return Err(Error::ProcessFailed(program, exit_status.code().unwrap_or(-1)));
+        }
+
+        // Copy the generated file system into the partition
+        if !self.output.is_file() {
+            return Err(self.error(format!("Command didn't generate {}", self.output.to_str().unwrap())));
+        }
+
+        let output = File::open(&self.output)?;
+        let length = output.metadata()?.len();
+        if length > self.size {
+            return Err(self.error(format!("Generated file system ({} bytes) is larger than the partition ({} bytes)",
+                length, self.size)));
+        }
+
+        let mut image = OpenOptions::new().write(true).open(&self.image)?;
+        image.seek(SeekFrom::Start(self.offset))?;
+        io::copy(&mut BufReader::with_capacity(COPY_BUFFER_SIZE, output), &mut image)?;
+        image.flush()?;
+
+        if !self.keep_intermediate {
+            remove_dir_all(&self.directory)?;
+            remove_file(&self.output)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,712 @@
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use colorful::{Color, Colorful};
+use log::debug;
+use crate::config::PartitionConfig;
+use crate::error::Error;
+use crate::image::{COPY_BUFFER_SIZE, FileSystemWriter};
+use crate::image::gpt::derive_guid;
+
+const SUPERBLOCK_OFFSET: u64 = 1024;
+const GROUP_DESCRIPTOR_SIZE: u64 = 32;
+const INODE_SIZE: u64 = 128;
+/// The space of the partition per inode, used to calculate the inode count like `mke2fs`
+const BYTES_PER_INODE: u64 = 8192;
+const ROOT_INODE: u32 = 2;
+const LOST_AND_FOUND_INODE: u32 = 11;
+/// The first inode, which isn't reserved
+const FIRST_INODE: u32 = 11;
+const DIRECT_BLOCKS: usize = 12;
+
+const FEATURE_INCOMPAT_FILETYPE: u32 = 0x0002;
+const FEATURE_RO_COMPAT_LARGE_FILE: u32 = 0x0002;
+const MODE_DIRECTORY: u16 = 0o040755;
+const MODE_REGULAR_FILE: u16 = 0o100644;
+const FILE_TYPE_REGULAR_FILE: u8 = 1;
+const FILE_TYPE_DIRECTORY: u8 = 2;
+
+/// A file or directory in the file system tree, which is written into the image when the writer is finished
+enum Node {
+    Directory(BTreeMap<String, Node>),
+    File(PathBuf)
+}
+
+/// The geometry of the file system. Each block group starts with a copy of the superblock and the group descriptor
+/// table (no sparse superblocks), followed by the block bitmap, the inode bitmap and the inode table.
+struct Geometry {
+    block_size: u64,
+    blocks_count: u32,
+    first_data_block: u32,
+    blocks_per_group: u32,
+    groups: u32,
+    inodes_per_group: u32,
+    group_descriptor_blocks: u32,
+    inode_table_blocks: u32
+}
+
+impl Geometry {
+    fn new(size: u64, inodes: u64) -> Result<Self, String> {
+        // Small file systems use 1 KiB blocks like `mke2fs`, so the first data block follows the boot block
+        let block_size = if size >= 64 * 1024 * 1024 { 4096 } else { 1024 };
+        let mut blocks_count = u32::try_from(size / block_size).map_err(|_| String::from("Partition is too large"))?;
+        let first_data_block = if block_size == 1024 { 1 } else { 0 };
+        let blocks_per_group = 8 * block_size as u32;
+        let inodes_per_block = (block_size / INODE_SIZE) as u32;
+        loop {
+            let groups = (blocks_count.saturating_sub(first_data_block)).div_ceil(blocks_per_group).max(1);
+            let inodes = (size / BYTES_PER_INODE).max(inodes);
+            let inodes_per_group = (inodes.div_ceil(groups as u64) as u32).next_multiple_of(inodes_per_block);
+            if inodes_per_group > blocks_per_group {
+                return Err(format!("Partition can't hold {} files and directories", inodes - FIRST_INODE as u64));
+            }
+
+            let geometry = Geometry {
+                block_size,
+                blocks_count,
+                first_data_block,
+                blocks_per_group,
+                groups,
+                inodes_per_group,
+                group_descriptor_blocks: (groups as u64 * GROUP_DESCRIPTOR_SIZE).div_ceil(block_size) as u32,
+                inode_table_blocks: inodes_per_group / inodes_per_block
+            };
+
+            // Drop the last group, if it's too small for its metadata
+            let last_group_blocks = geometry.group_blocks(groups - 1);
+            if last_group_blocks > geometry.overhead() {
+                return Ok(geometry);
+            } else if groups == 1 {
+                return Err(String::from("Partition is too small for the file system metadata"));
+            }
+            blocks_count -= last_group_blocks;
+        }
+    }
+
+    fn group_start(&self, group: u32) -> u32 {
+        self.first_data_block + group * self.blocks_per_group
+    }
+
+    fn group_blocks(&self, group: u32) -> u32 {
+        (self.blocks_count - self.group_start(group)).min(self.blocks_per_group)
+    }
+
+    /// The count of blocks at the start of each group used by the metadata
+    fn overhead(&self) -> u32 {
+        3 + self.group_descriptor_blocks + self.inode_table_blocks
+    }
+
+    fn inode_count(&self) -> u32 {
+        self.inodes_per_group * self.groups
+    }
+}
+
+/// Writer of an ext2 file system (revision 1 with 128-byte inodes and file types in the directory entries) into the
+/// partition of the image file. The files are collected into a tree and the whole file system is written when the
+/// writer is finished.
+pub(crate) struct Ext2Writer {
+    file: File,
+    offset: u64,
+    size: u64,
+    name: String,
+    label: Option<String>,
+    root: BTreeMap<String, Node>
+}
+
+impl Ext2Writer {
+    pub(crate) fn new<P: AsRef<Path>>(image: P, offset: u64, size: u64,
+                                      partition: &PartitionConfig) -> Result<Self, Error> {
+        Ok(Ext2Writer {
+            file: OpenOptions::new().read(true).write(true).open(image)?,
+            offset,
+            size,
+            name: partition.name.clone(),
+            label: partition.label.clone(),
+            root: BTreeMap::new()
+        })
+    }
+
+    fn error(&self, message: String) -> Error {
+        Error::PartitionError(self.name.clone(), message)
+    }
+
+    /// Return the directory of the path in the tree and create the missing directories
+    fn directory(&mut self, directory: &Path) -> Result<&mut BTreeMap<String, Node>, Error> {
+        let mut current = &mut self.root;
+        for component in directory.components() {
+            let Component::Normal(name) = component else {
+                continue;
+            };
+
+            let node = current.entry(name.to_str().unwrap().to_owned()).or_insert(Node::Directory(BTreeMap::new()));
+            current = match node {
+                Node::Directory(children) => children,
+                Node::File(_) => {
+                    return Err(Error::PartitionError(self.name.clone(), format!("'{}' is no directory",
+                        directory.to_str().unwrap())));
+                }
+            };
+        }
+        Ok(current)
+    }
+}
+
+impl FileSystemWriter for Ext2Writer {
+    fn name(&self) -> &'static str {
+        "ext2"
+    }
+
+    fn create_directory(&mut self, directory: &Path) -> Result<(), Error> {
+        self.directory(directory)?;
+        Ok(())
+    }
+
+    fn copy_into(&mut self, host_file: &Path, image_file: &Path) -> Result<(), Error> {
+        debug!(
+            "Move {} as {} into ext2 partition",
+            host_file.to_str().unwrap().gradient(Color::Cyan),
+            image_file.to_str().unwrap().gradient(Color::Red)
+        );
+
+        if !host_file.is_file() {
+            return Err(Error::FileNotFound(host_file.to_str().unwrap().to_owned()));
+        }
+
+        let name = image_file.file_name()
+            .ok_or(Error::InvalidParameter(String::from("image_file")))?
+            .to_str().unwrap().to_owned();
+        let directory = self.directory(image_file.parent().unwrap_or(Path::new("")))?;
+        if let Some(Node::Directory(_)) = directory.get(&name) {
+            return Err(Error::InvalidParameter(String::from("image_file")));
+        }
+        directory.insert(name, Node::File(host_file.to_path_buf()));
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<(), Error> {
+        if self.root.contains_key("lost+found") {
+            return Err(self.error(String::from("'lost+found' is reserved")));
+        }
+
+        fn count(nodes: &BTreeMap<String, Node>) -> u64 {
+            nodes.values().map(|node| match node {
+                Node::Directory(children) => 1 + count(children),
+                Node::File(_) => 1
+            }).sum()
+        }
+
+        let inodes = FIRST_INODE as u64 + count(&self.root);
+        let geometry = Geometry::new(self.size, inodes).map_err(|error| self.error(error))?;
+        let root = std::mem::take(&mut self.root);
+        let mut builder = Builder::new(*self, geometry);
+        builder.write_directory(ROOT_INODE, ROOT_INODE, &root)?;
+        builder.write_directory(LOST_AND_FOUND_INODE, ROOT_INODE, &BTreeMap::new())?;
+        builder.write_metadata()
+    }
+}
+
+/// Builder writing the data and metadata of the file system. Blocks and inodes are allocated sequentially.
+struct Builder {
+    writer: Ext2Writer,
+    geometry: Geometry,
+    block_bitmaps: Vec<Vec<u8>>,
+    inodes: BTreeMap<u32, [u8; INODE_SIZE as usize]>,
+    used_directories: Vec<u16>,
+    next_block: u32,
+    next_inode: u32,
+    large_files: bool,
+    time: u32
+}
+
+impl Builder {
+    fn new(writer: Ext2Writer, geometry: Geometry) -> Self {
+        // Mark the metadata blocks and the bits after the end of the last group as used
+        let block_bitmaps = (0..geometry.groups).map(|group| {
+            let mut bitmap = vec![0; geometry.block_size as usize];
+            let group_blocks = geometry.group_blocks(group);
+            for bit in (0..geometry.overhead()).chain(group_blocks..geometry.blocks_per_group) {
+                bitmap[bit as usize / 8] |= 1 << (bit % 8);
+            }
+            bitmap
+        }).collect();
+
+        Builder {
+            writer,
+            used_directories: vec![0; geometry.groups as usize],
+            next_block: geometry.first_data_block,
+            geometry,
+            block_bitmaps,
+            inodes: BTreeMap::new(),
+            next_inode: FIRST_INODE + 1,
+            large_files: false,
+            time: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as u32
+        }
+    }
+
+    fn allocate_block(&mut self) -> Result<u32, Error> {
+        loop {
+            if self.next_block >= self.geometry.blocks_count {
+                return Err(self.writer.error(String::from("No space left for the files")));
+            }
+
+            let group = (self.next_block - self.geometry.first_data_block) / self.geometry.blocks_per_group;
+            let bit = self.next_block - self.geometry.group_start(group);
+            if bit < self.geometry.overhead() {
+                self.next_block = self.geometry.group_start(group) + self.geometry.overhead();
+                continue;
+            }
+
+            self.block_bitmaps[group as usize][bit as usize / 8] |= 1 << (bit % 8);
+            self.next_block += 1;
+            return Ok(self.next_block - 1);
+        }
+    }
+
+    fn allocate_inode(&mut self) -> Result<u32, Error> {
+        if self.next_inode > self.geometry.inode_count() {
+            return Err(self.writer.error(String::from("No inodes left for the files")));
+        }
+        self.next_inode += 1;
+        Ok(self.next_inode - 1)
+    }
+
+    fn write_at(&mut self, position: u64, data: &[u8]) -> Result<(), Error> {
+        self.writer.file.seek(SeekFrom::Start(self.writer.offset + position))?;
+        self.writer.file.write_all(data)?;
+        Ok(())
+    }
+
+    fn write_block(&mut self, block: u32, data: &[u8]) -> Result<(), Error> {
+        self.write_at(block as u64 * self.geometry.block_size, data)
+    }
+
+    /// Write the indirect block with the pointers to the blocks. Blocks of a higher level point to indirect blocks of
+    /// the lower level.
+    fn write_indirect(&mut self, blocks: &[u32], level: u32, count: &mut u32) -> Result<u32, Error> {
+        let pointers_per_block = (self.geometry.block_size / 4) as usize;
+        let block = self.allocate_block()?;
+        *count += 1;
+
+        let pointers = if level == 1 {
+            blocks.to_vec()
+        } else {
+            blocks.chunks(pointers_per_block.pow(level - 1))
+                .map(|blocks| self.write_indirect(blocks, level - 1, count))
+                .collect::<Result<Vec<_>, _>>()?
+        };
+
+        let mut data = vec![0; self.geometry.block_size as usize];
+        for (index, pointer) in pointers.iter().enumerate() {
+            data[index * 4..index * 4 + 4].copy_from_slice(&pointer.to_le_bytes());
+        }
+        self.write_block(block, &data)?;
+        Ok(block)
+    }
+
+    /// Write the data of the inode into newly allocated blocks and store the inode with the block pointers
+    fn write_inode(&mut self, inode: u32, mode: u16, links: u16, size: u64, data: &mut dyn Read) -> Result<(), Error> {
+        let block_size = self.geometry.block_size as usize;
+        let mut blocks = Vec::with_capacity(size.div_ceil(block_size as u64) as usize);
+        let mut buffer = vec![0; block_size];
+        for _ in 0..size.div_ceil(block_size as u64) {
+            buffer.fill(0);
+            let mut length = 0;
+            while length < block_size {
+                match data.read(&mut buffer[length..])? {
+                    0 => break,
+                    read => length += read
+                }
+            }
+
+            let block = self.allocate_block()?;
+            self.write_block(block, &buffer)?;
+            blocks.push(block);
+        }
+
+        // Map the blocks with the direct pointers and the single, double and triple indirect blocks
+        let mut pointers = [0; 15];
+        let mut indirect_blocks = 0;
+        let (direct, mut remaining) = blocks.split_at(blocks.len().min(DIRECT_BLOCKS));
+        pointers[..direct.len()].copy_from_slice(direct);
+        for level in 1..=3 {
+            if remaining.is_empty() {
+                break;
+            }
+
+            let (mapped, rest) = remaining.split_at(remaining.len().min((block_size / 4).pow(level)));
+            pointers[DIRECT_BLOCKS - 1 + level as usize] = self.write_indirect(mapped, level, &mut indirect_blocks)?;
+            remaining = rest;
+        }
+
+        if !remaining.is_empty() {
+            return Err(self.writer.error(format!("File of inode {} is too large", inode)));
+        }
+
+        let mut entry = [0; INODE_SIZE as usize];
+        entry[0..2].copy_from_slice(&mode.to_le_bytes());
+        entry[4..8].copy_from_slice(&(size as u32).to_le_bytes());
+        for offset in [8, 12, 16] {
+            entry[offset..offset + 4].copy_from_slice(&self.time.to_le_bytes());
+        }
+        entry[26..28].copy_from_slice(&links.to_le_bytes());
+        let sectors = (blocks.len() as u64 + indirect_blocks as u64) * self.geometry.block_size / 512;
+        entry[28..32].copy_from_slice(&(sectors as u32).to_le_bytes());
+        for (index, pointer) in pointers.iter().enumerate() {
+            entry[40 + index * 4..44 + index * 4].copy_from_slice(&pointer.to_le_bytes());
+        }
+        entry[108..112].copy_from_slice(&((size >> 32) as u32).to_le_bytes());
+        self.large_files |= size > i32::MAX as u64;
+        self.inodes.insert(inode, entry);
+        Ok(())
+    }
+
+    fn write_file(&mut self, inode: u32, host_file: &Path) -> Result<(), Error> {
+        let file = File::open(host_file)?;
+        let size = file.metadata()?.len();
+        let mut reader = BufReader::with_capacity(COPY_BUFFER_SIZE, file);
+        self.write_inode(inode, MODE_REGULAR_FILE, 1, size, &mut reader)
+    }
+
+    /// Write the directory with its entries and the children recursively
+    fn write_directory(&mut self, inode: u32, parent: u32, children: &BTreeMap<String, Node>) -> Result<(), Error> {
+        let mut entries = vec![(inode, FILE_TYPE_DIRECTORY, "."), (parent, FILE_TYPE_DIRECTORY, "..")];
+        if inode == ROOT_INODE {
+            entries.push((LOST_AND_FOUND_INODE, FILE_TYPE_DIRECTORY, "lost+found"));
+        }
+
+        let mut nodes = Vec::new();
+        for (name, node) in children {
+            let child = self.allocate_inode()?;
+            let file_type = match node {
+                Node::Directory(_) => FILE_TYPE_DIRECTORY,
+                Node::File(_) => FILE_TYPE_REGULAR_FILE
+            };
+            entries.push((child, file_type, name));
+            nodes.push((child, node));
+        }
+
+        // Directory entries don't span blocks, the last entry of each block is extended to the end of the block
+        let block_size = self.geometry.block_size as usize;
+        let mut data: Vec<u8> = Vec::new();
+        let mut last_entry = 0;
+        for (entry_inode, file_type, name) in &entries {
+            if name.len() > 255 {
+                return Err(self.writer.error(format!("Name '{}' is longer than 255 bytes", name)));
+            }
+
+            let length = (8 + name.len()).next_multiple_of(4);
+            let block_end = data.len().next_multiple_of(block_size);
+            if !data.is_empty() && data.len() + length > block_end {
+                extend_entry(&mut data, last_entry, block_end);
+            }
+
+            last_entry = data.len();
+            data.extend((*entry_inode).to_le_bytes());
+            data.extend((length as u16).to_le_bytes());
+            data.extend([name.len() as u8, *file_type]);
+            data.extend(name.as_bytes());
+            data.resize(last_entry + length, 0);
+        }
+        let end = data.len().next_multiple_of(block_size);
+        extend_entry(&mut data, last_entry, end);
+
+        let directories = entries.iter().skip(2).filter(|(_, file_type, _)| *file_type == FILE_TYPE_DIRECTORY).count();
+        self.write_inode(inode, MODE_DIRECTORY, 2 + directories as u16, data.len() as u64, &mut data.as_slice())?;
+        self.used_directories[((inode - 1) / self.geometry.inodes_per_group) as usize] += 1;
+
+        for (child, node) in nodes {
+            match node {
+                Node::Directory(children) => self.write_directory(child, inode, children)?,
+                Node::File(host_file) => self.write_file(child, host_file)?
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the superblock, the group descriptor table, the bitmaps and the inode table of each group
+    fn write_metadata(mut self) -> Result<(), Error> {
+        let geometry = &self.geometry;
+        let inodes_per_group = geometry.inodes_per_group;
+        let mut descriptors = vec![0; (geometry.group_descriptor_blocks as u64 * geometry.block_size) as usize];
+        let mut inode_bitmaps = Vec::new();
+        let (mut free_blocks, mut free_inodes) = (0, 0);
+        for group in 0..geometry.groups {
+            let start = geometry.group_start(group);
+            let bitmap = &self.block_bitmaps[group as usize];
+            let group_free_blocks = (0..geometry.group_blocks(group))
+                .filter(|bit| bitmap[*bit as usize / 8] & (1 << (bit % 8)) == 0)
+                .count() as u32;
+
+            // The inodes are allocated sequentially, so all inodes before the next inode are used
+            let mut inode_bitmap = vec![0; geometry.block_size as usize];
+            let first_inode = group * inodes_per_group + 1;
+            let used_inodes = self.next_inode.saturating_sub(first_inode).min(inodes_per_group);
+            for bit in (0..used_inodes).chain(inodes_per_group..8 * geometry.block_size as u32) {
+                inode_bitmap[bit as usize / 8] |= 1 << (bit % 8);
+            }
+            inode_bitmaps.push(inode_bitmap);
+
+            let descriptor = &mut descriptors[(group as u64 * GROUP_DESCRIPTOR_SIZE) as usize..];
+            let bitmap_block = start + 1 + geometry.group_descriptor_blocks;
+            descriptor[0..4].copy_from_slice(&bitmap_block.to_le_bytes());
+            descriptor[4..8].copy_from_slice(&(bitmap_block + 1).to_le_bytes());
+            descriptor[8..12].copy_from_slice(&(bitmap_block + 2).to_le_bytes());
+            descriptor[12..14].copy_from_slice(&(group_free_blocks as u16).to_le_bytes());
+            descriptor[14..16].copy_from_slice(&((inodes_per_group - used_inodes) as u16).to_le_bytes());
+            descriptor[16..18].copy_from_slice(&self.used_directories[group as usize].to_le_bytes());
+            free_blocks += group_free_blocks;
+            free_inodes += inodes_per_group - used_inodes;
+        }
+
+        let mut superblock = [0; 1024];
+        let fields: [(usize, u32); 17] = [
+            (0, geometry.inode_count()),
+            (4, geometry.blocks_count),
+            (12, free_blocks),
+            (16, free_inodes),
+            (20, geometry.first_data_block),
+            (24, geometry.block_size.trailing_zeros() - 10),
+            (28, geometry.block_size.trailing_zeros() - 10),
+            (32, geometry.blocks_per_group),
+            (36, geometry.blocks_per_group),
+            (40, inodes_per_group),
+            (44, self.time),
+            (48, self.time),
+            (64, self.time),
+            (76, 1),
+            (84, FIRST_INODE),
+            (96, FEATURE_INCOMPAT_FILETYPE),
+            (100, if self.large_files { FEATURE_RO_COMPAT_LARGE_FILE } else { 0 })
+        ];
+        for (offset, value) in fields {
+            superblock[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        superblock[54..56].copy_from_slice(&u16::MAX.to_le_bytes());
+        superblock[56..58].copy_from_slice(&0xEF53u16.to_le_bytes());
+        superblock[58..60].copy_from_slice(&1u16.to_le_bytes());
+        superblock[60..62].copy_from_slice(&1u16.to_le_bytes());
+        superblock[88..90].copy_from_slice(&(INODE_SIZE as u16).to_le_bytes());
+        superblock[104..120].copy_from_slice(&derive_guid(&format!("ext2-{}", self.writer.name)));
+        if let Some(label) = self.writer.label.as_ref() {
+            superblock[120..120 + label.len().min(16)].copy_from_slice(&label.as_bytes()[..label.len().min(16)]);
+        }
+
+        // Write the metadata of each group. The superblock of the first group is always at byte 1024.
+        let block_size = geometry.block_size;
+        let groups = geometry.groups;
+        let descriptor_blocks = geometry.group_descriptor_blocks;
+        for group in 0..groups {
+            let start = self.geometry.group_start(group);
+            superblock[90..92].copy_from_slice(&(group as u16).to_le_bytes());
+            let superblock_position = (start as u64 * block_size).max(SUPERBLOCK_OFFSET);
+            self.write_at(superblock_position, &superblock)?;
+            self.write_block(start + 1, &descriptors)?;
+
+            let block_bitmap = self.block_bitmaps[group as usize].clone();
+            self.write_block(start + 1 + descriptor_blocks, &block_bitmap)?;
+            self.write_block(start + 2 + descriptor_blocks, &inode_bitmaps[group as usize])?;
+
+            let mut inode_table = vec![0; (inodes_per_group as u64 * INODE_SIZE) as usize];
+            let first_inode = group * inodes_per_group + 1;
+            for (inode, entry) in self.inodes.range(first_inode..first_inode + inodes_per_group) {
+                let offset = ((inode - first_inode) as u64 * INODE_SIZE) as usize;
+                inode_table[offset..offset + INODE_SIZE as usize].copy_from_slice(entry);
+            }
+            self.write_block(start + 3 + descriptor_blocks, &inode_table)?;
+        }
+        self.writer.file.flush()?;
+        Ok(())
+    }
+}
+
+/// Extend the record length of the directory entry at the offset to the end position
+fn extend_entry(data: &mut Vec<u8>, entry: usize, end: usize) {
+    let length = u16::from_le_bytes([data[entry + 4], data[entry + 5]]) as usize + end - data.len();
+    data[entry + 4..entry + 6].copy_from_slice(&(length as u16).to_le_bytes());
+    data.resize(end, 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::path::Path;
+    use std::process::Command;
+    use crate::config::PartitionConfig;
+    use crate::error::Error;
+    use crate::image::{FileSystemKind, FileSystemWriter};
+    use crate::image::ext2::{Builder, Ext2Writer, extend_entry, Geometry, MODE_REGULAR_FILE, Node, ROOT_INODE};
+    use crate::utils::find_in_path;
+
+    const MIB: u64 = 1024 * 1024;
+
+    fn writer(image: &Path, size: u64) -> Ext2Writer {
+        File::create(image).unwrap().set_len(size).unwrap();
+        let partition = PartitionConfig {
+            name: String::from("data"),
+            filesystem: FileSystemKind::Ext2,
+            label: Some(String::from("DATA")),
+            ..PartitionConfig::default()
+        };
+        Ext2Writer::new(image, 0, size, &partition).unwrap()
+    }
+
+    fn pointer(data: &[u8], index: usize) -> u32 {
+        u32::from_le_bytes(data[index * 4..index * 4 + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn geometry_and_group_count() {
+        let geometry = Geometry::new(4 * MIB, 0).unwrap();
+        assert_eq!((geometry.block_size, geometry.blocks_count, geometry.first_data_block), (1024, 4096, 1));
+        assert_eq!((geometry.groups, geometry.inodes_per_group, geometry.inode_table_blocks), (1, 512, 64));
+        assert_eq!(geometry.overhead(), 68);
+
+        let geometry = Geometry::new(40 * MIB, 0).unwrap();
+        assert_eq!((geometry.block_size, geometry.groups, geometry.inodes_per_group), (1024, 5, 1024));
+        assert_eq!(geometry.group_blocks(4), 40960 - geometry.group_start(4));
+
+        let geometry = Geometry::new(300 * MIB, 0).unwrap();
+        assert_eq!((geometry.block_size, geometry.blocks_count, geometry.first_data_block), (4096, 76800, 0));
+        assert_eq!((geometry.groups, geometry.inodes_per_group, geometry.inode_table_blocks), (3, 12800, 400));
+
+        // The last group with 10 blocks can't hold its metadata and is dropped
+        let geometry = Geometry::new(8203 * 1024, 0).unwrap();
+        assert_eq!((geometry.blocks_count, geometry.groups, geometry.inodes_per_group), (8193, 1, 1032));
+
+        // More files than the default inode count
+        let geometry = Geometry::new(4 * MIB, 2000).unwrap();
+        assert_eq!(geometry.inode_count(), 2000);
+    }
+
+    #[test]
+    fn geometry_errors() {
+        let error = Geometry::new(4096, 0).err().unwrap();
+        assert_eq!(error, "Partition is too small for the file system metadata");
+        let error = Geometry::new(4 * MIB, 10000).err().unwrap();
+        assert_eq!(error, "Partition can't hold 9989 files and directories");
+
+        let directory = tempfile::tempdir().unwrap();
+        let image = directory.path().join("image.img");
+        let result = Box::new(writer(&image, 4096)).finish();
+        assert!(matches!(result, Err(Error::PartitionError(name, _)) if name == "data"));
+    }
+
+    #[test]
+    fn maps_blocks_with_indirect_blocks() {
+        let directory = tempfile::tempdir().unwrap();
+        let image = directory.path().join("image.img");
+        let mut builder = Builder::new(writer(&image, 4 * MIB), Geometry::new(4 * MIB, 0).unwrap());
+
+        // 12 direct blocks, 256 blocks of the single indirect block and 10 blocks of the double indirect block
+        let data: Vec<u8> = (0..278 * 1024).map(|index| (index % 251) as u8).collect();
+        builder.write_inode(12, MODE_REGULAR_FILE, 1, data.len() as u64, &mut data.as_slice()).unwrap();
+        let inode = builder.inodes[&12];
+        let pointers: Vec<u32> = (0..15).map(|index| pointer(&inode[40..], index)).collect();
+        assert_eq!(pointer(&inode, 7), (278 + 3) * 2);
+        assert_eq!(pointers[14], 0);
+
+        let image_data = fs::read(&image).unwrap();
+        let block = |block: u32| &image_data[block as usize * 1024..(block as usize + 1) * 1024];
+        let mut blocks = pointers[..12].to_vec();
+        blocks.extend((0..256).map(|index| pointer(block(pointers[12]), index)));
+        let double = block(pointers[13]);
+        assert_eq!(pointer(double, 1), 0);
+        blocks.extend((0..10).map(|index| pointer(block(pointer(double, 0)), index)));
+        assert_eq!(pointer(block(pointer(double, 0)), 10), 0);
+
+        let mapped: Vec<u8> = blocks.iter().flat_map(|number| block(*number).to_vec()).collect();
+        assert_eq!(mapped, data);
+    }
+
+    #[test]
+    fn maps_blocks_with_triple_indirect_block() {
+        let directory = tempfile::tempdir().unwrap();
+        let image = directory.path().join("image.img");
+        let mut builder = Builder::new(writer(&image, 4 * MIB), Geometry::new(4 * MIB, 0).unwrap());
+
+        let mut count = 0;
+        let triple = builder.write_indirect(&[100, 101, 102], 3, &mut count).unwrap();
+        assert_eq!(count, 3);
+
+        let image_data = fs::read(&image).unwrap();
+        let block = |block: u32| &image_data[block as usize * 1024..(block as usize + 1) * 1024];
+        let single = block(pointer(block(pointer(block(triple), 0)), 0));
+        assert_eq!((0..4).map(|index| pointer(single, index)).collect::<Vec<_>>(), [100, 101, 102, 0]);
+        assert_eq!(pointer(block(triple), 1), 0);
+    }
+
+    #[test]
+    fn extends_directory_entries_to_block_end() {
+        let mut data = vec![2, 0, 0, 0, 12, 0, 1, 2, b'.', 0, 0, 0];
+        extend_entry(&mut data, 0, 1024);
+        assert_eq!(data.len(), 1024);
+        assert_eq!(u16::from_le_bytes([data[4], data[5]]), 1024);
+
+        // Entries of 60 bytes don't fill the blocks of the directory completely
+        let directory = tempfile::tempdir().unwrap();
+        let image = directory.path().join("image.img");
+        let host_file = directory.path().join("file");
+        fs::write(&host_file, []).unwrap();
+        let names: Vec<String> = (0..60).map(|index| format!("{:050}", index)).collect();
+        let children = names.iter().map(|name| (name.clone(), Node::File(host_file.clone()))).collect();
+        let mut builder = Builder::new(writer(&image, 4 * MIB), Geometry::new(4 * MIB, 100).unwrap());
+        builder.write_directory(ROOT_INODE, ROOT_INODE, &children).unwrap();
+
+        let inode = builder.inodes[&ROOT_INODE];
+        let size = u32::from_le_bytes(inode[4..8].try_into().unwrap()) as usize;
+        assert_eq!(size, 4 * 1024);
+        let image_data = fs::read(&image).unwrap();
+        let mut entries = Vec::new();
+        for index in 0..size / 1024 {
+            let block = pointer(&inode[40..], index) as usize;
+            let data = &image_data[block * 1024..(block + 1) * 1024];
+            let mut offset = 0;
+            while offset < 1024 {
+                let length = u16::from_le_bytes([data[offset + 4], data[offset + 5]]) as usize;
+                let name_length = data[offset + 6] as usize;
+                assert!(length >= 8 + name_length && offset + length <= 1024);
+                entries.push(String::from_utf8(data[offset + 8..offset + 8 + name_length].to_vec()).unwrap());
+                offset += length;
+            }
+            assert_eq!(offset, 1024);
+        }
+        let expected = [".", "..", "lost+found"].into_iter().chain(names.iter().map(String::as_str));
+        assert_eq!(entries, expected.collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn file_system_passes_e2fsck() {
+        let Some(e2fsck) = find_in_path("e2fsck") else {
+            eprintln!("e2fsck isn't installed, the file system isn't checked");
+            return;
+        };
+
+        let directory = tempfile::tempdir().unwrap();
+        let image = directory.path().join("image.img");
+        let kernel = directory.path().join("kernel.bin");
+        let kernel_data: Vec<u8> = (0..600 * 1024).map(|index| (index % 251) as u8).collect();
+        fs::write(&kernel, &kernel_data).unwrap();
+        let readme = directory.path().join("readme.txt");
+        fs::write(&readme, "Hello ext2").unwrap();
+
+        let mut writer = Box::new(writer(&image, 40 * MIB));
+        writer.copy_into(&kernel, Path::new("boot/kernel.bin")).unwrap();
+        writer.copy_into(&readme, Path::new("readme.txt")).unwrap();
+        writer.create_directory(Path::new("data/empty")).unwrap();
+        writer.finish().unwrap();
+
+        let status = Command::new(e2fsck).arg("-fn").arg(&image).output().unwrap().status;
+        assert!(status.success());
+
+        let Some(debugfs) = find_in_path("debugfs") else {
+            return;
+        };
+        let cat = |path: &str| {
+            Command::new(&debugfs).arg("-R").arg(format!("cat {}", path)).arg(&image).output().unwrap().stdout
+        };
+        assert_eq!(cat("/boot/kernel.bin"), kernel_data);
+        assert_eq!(cat("/readme.txt"), b"Hello ext2");
+    }
+}
@@ -0,0 +1,169 @@
+use crate::{
+    config::VolumeConfig,
+    error::Error,
+    image::{
+        FileSystemWriter,
+        COPY_BUFFER_SIZE,
+    },
+};
+use colorful::{
+    Color,
+    Colorful,
+};
+use fatfs::{
+    format_volume,
+    FatType,
+    FileSystem,
+    FormatVolumeOptions,
+    FsOptions,
+};
+use fscommon::{
+    BufStream,
+    StreamSlice,
+};
+use log::debug;
+use std::{
+    fs,
+    fs::File,
+    io,
+    io::{
+        BufReader,
+        Read,
+        Seek,
+        SeekFrom,
+        Write,
+    },
+    path::Path,
+};
+
+/// The minimal count of sectors of a FAT32 volume. FAT32 requires at least 65525 clusters, which are formatted with
+/// one sector per cluster at this size (plus the reserved sectors and both FATs).
+pub(crate) const MIN_FAT32_SECTORS: u32 = 66600;
+
+/// Convert the name into a fixed-size field of the boot sector or the volume descriptor, padded with spaces
+pub(crate) fn padded_name<const LENGTH: usize>(name: &str) -> [u8; LENGTH] {
+    let mut padded = [b' '; LENGTH];
+    name.bytes()
+        .take(LENGTH)
+        .enumerate()
+        .for_each(|(index, byte)| padded[index] = byte.to_ascii_uppercase());
+    padded
+}
+
+/// Writer of a FAT32 file system at the offset of the image file (the start of the file or a partition)
+pub struct FatWriter {
+    file_system: FileSystem<BufStream<StreamSlice<File>>>,
+}
+
+impl FatWriter {
+    pub fn new<F: AsRef<Path>>(
+        file: F, offset: u64, block_size: u16, block_count: u32, volume: &VolumeConfig,
+    ) -> Result<FatWriter, Error> {
+        // Create the zeroed file, so no data of a previous image remains. The file is extended without writing, so the
+        // file system creates a sparse file and the unused blocks take no space on the host. Volumes in a partition
+        // are formatted in the existing disk image file.
+        let size = (block_count as u64) * (block_size as u64);
+        if block_count < MIN_FAT32_SECTORS {
+            return Err(Error::FatVolumeTooSmall(size, MIN_FAT32_SECTORS as u64 * block_size as u64));
+        }
+
+        if offset == 0 {
+            File::create(&file)?.set_len(size)?;
+        } else {
+            let length = fs::metadata(&file)?.len();
+            if length < offset + size {
+                return Err(Error::ImageTooSmall(file.as_ref().to_str().unwrap().to_owned(), length, offset + size));
+            }
+        }
+
+        // Format Volume
+        let file = fs::OpenOptions::new().read(true).write(true).open(file)?;
+        let mut file_buffer = BufStream::new(StreamSlice::new(file, offset, offset + size)?);
+        let mut options = FormatVolumeOptions::new()
+            .fat_type(FatType::Fat32)
+            .bytes_per_sector(block_size)
+            .total_sectors(block_count);
+        if let Some(label) = volume.label.as_ref() {
+            options = options.volume_label(padded_name(label));
+        }
+
+        if let Some(id) = volume.id {
+            options = options.volume_id(id);
+        }
+        format_volume(&mut file_buffer, options)?;
+
+        // Write OEM name into the boot sector and the backup boot sector, because fatfs always writes its own name
+        if let Some(oem_name) = volume.oem_name.as_ref() {
+            let oem_name: [u8; 8] = padded_name(oem_name);
+            let mut backup_boot_sector = [0; 2];
+            file_buffer.seek(SeekFrom::Start(50))?;
+            file_buffer.read_exact(&mut backup_boot_sector)?;
+
+            let backup_offset = u16::from_le_bytes(backup_boot_sector) as u64 * block_size as u64;
+            for offset in [0, backup_offset] {
+                file_buffer.seek(SeekFrom::Start(offset + 3))?;
+                file_buffer.write_all(&oem_name)?;
+            }
+            file_buffer.flush()?;
+            file_buffer.seek(SeekFrom::Start(0))?;
+        }
+
+        let file_system = FileSystem::new(file_buffer, FsOptions::new().update_accessed_date(true))?;
+        Ok(Self { file_system })
+    }
+}
+
+impl FileSystemWriter for FatWriter {
+    fn name(&self) -> &'static str {
+        "FAT32"
+    }
+
+    fn create_directory(&mut self, directory: &Path) -> Result<(), Error> {
+        if directory.to_str().unwrap().is_empty() {
+            return Ok(());
+        }
+
+        if let Some(parent) = directory.parent() {
+            self.create_directory(parent)?;
+        }
+
+        let root_directory = self.file_system.root_dir();
+        root_directory.create_dir(directory.to_str().unwrap())?;
+        Ok(())
+    }
+
+    fn copy_into(&mut self, host_file: &Path, image_file: &Path) -> Result<(), Error> {
+        debug!(
+            "Move {} as {} into image",
+            host_file.to_str().unwrap().gradient(Color::Cyan),
+            image_file.to_str().unwrap().gradient(Color::Red)
+        );
+
+        // Host directories are created as directories inside the volume
+        if host_file.is_dir() {
+            return self.create_directory(image_file);
+        }
+
+        // Open host file
+        let file = fs::OpenOptions::new().read(true).open(host_file)?;
+        let mut reader = BufReader::with_capacity(COPY_BUFFER_SIZE, file);
+
+        // Ensure directory + create file and stream the host file into it
+        if let Some(parent) = image_file.parent() {
+            self.create_directory(parent)?;
+        }
+
+        let mut file = self
+            .file_system
+            .root_dir()
+            .create_file(image_file.to_str().unwrap())?;
+        io::copy(&mut reader, &mut file)?;
+        file.flush()?;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), Error> {
+        self.file_system.unmount()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,281 @@
+use std::fs::File;
+use std::path::Path;
+use std::io::{Seek, SeekFrom, Write};
+use sha2::{Digest, Sha256};
+use crate::error::Error;
+
+/// The size of the logical blocks of the disk
+pub(crate) const SECTOR_SIZE: u64 = 512;
+
+/// The alignment of the partitions (1 MiB)
+const PARTITION_ALIGNMENT: u64 = 1024 * 1024;
+const ENTRY_COUNT: u64 = 128;
+const ENTRY_SIZE: u64 = 128;
+const ENTRY_SECTORS: u64 = ENTRY_COUNT * ENTRY_SIZE / SECTOR_SIZE;
+
+pub(crate) const EFI_SYSTEM_PARTITION: &str = "C12A7328-F81F-11D2-BA4B-00A0C93EC93B";
+pub(crate) const BASIC_DATA_PARTITION: &str = "EBD0A0A2-B9E5-4433-87C0-68B6B72699C7";
+pub(crate) const LINUX_FILESYSTEM_PARTITION: &str = "0FC63DAF-8483-4772-8E79-3D69D8477DE4";
+
+/// Parse the GUID in the textual form (e.g. `C12A7328-F81F-11D2-BA4B-00A0C93EC93B`) into the mixed-endian binary
+/// form of GPT. The first three fields are stored little-endian.
+pub(crate) fn parse_guid(guid: &str) -> Option<[u8; 16]> {
+    let fields: Vec<&str> = guid.split('-').collect();
+    if fields.iter().map(|field| field.len()).collect::<Vec<_>>() != [8, 4, 4, 4, 12] {
+        return None;
+    }
+
+    let hex = fields.concat();
+    if !hex.bytes().all(|character| character.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let mut bytes = [0; 16];
+    for (index, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(index * 2..index * 2 + 2)?, 16).ok()?;
+    }
+    bytes[0..4].reverse();
+    bytes[4..6].reverse();
+    bytes[6..8].reverse();
+    Some(bytes)
+}
+
+/// Derive a GUID (version 4 layout) from the seed, so the GUIDs of the disk and partitions are reproducible
+pub(crate) fn derive_guid(seed: &str) -> [u8; 16] {
+    let hash = Sha256::digest(seed.as_bytes());
+    let mut guid: [u8; 16] = hash[..16].try_into().unwrap();
+    guid[7] = (guid[7] & 0x0F) | 0x40;
+    guid[8] = (guid[8] & 0x3F) | 0x80;
+    guid
+}
+
+/// A partition of the GPT disk
+#[derive(Clone, Debug)]
+pub(crate) struct GptPartition {
+    pub(crate) name: String,
+    pub(crate) type_guid: [u8; 16],
+    pub(crate) first_lba: u64,
+    pub(crate) last_lba: u64
+}
+
+impl GptPartition {
+    /// The offset of the partition in bytes
+    pub(crate) fn offset(&self) -> u64 {
+        self.first_lba * SECTOR_SIZE
+    }
+
+    /// The size of the partition in bytes
+    pub(crate) fn size(&self) -> u64 {
+        (self.last_lba - self.first_lba + 1) * SECTOR_SIZE
+    }
+}
+
+/// The GUID partition table of a disk image. The partitions are placed one after another, aligned to 1 MiB.
+pub(crate) struct GptDisk {
+    pub(crate) partitions: Vec<GptPartition>,
+    pub(crate) size: u64
+}
+
+impl GptDisk {
+    /// Place the partitions with the name, type GUID and size in bytes on the disk
+    pub(crate) fn new(partitions: &[(String, [u8; 16], u64)]) -> Self {
+        let mut disk = GptDisk { partitions: Vec::new(), size: 0 };
+        let mut offset = PARTITION_ALIGNMENT;
+        for (name, type_guid, size) in partitions {
+            let sectors = size.div_ceil(SECTOR_SIZE);
+            disk.partitions.push(GptPartition {
+                name: name.clone(),
+                type_guid: *type_guid,
+                first_lba: offset / SECTOR_SIZE,
+                last_lba: offset / SECTOR_SIZE + sectors - 1
+            });
+            offset += (sectors * SECTOR_SIZE).next_multiple_of(PARTITION_ALIGNMENT);
+        }
+
+        // Reserve space for the backup partition entries and header at the end of the disk
+        disk.size = offset + (ENTRY_SECTORS + 1) * SECTOR_SIZE;
+        disk
+    }
+
+    fn header(&self, current_lba: u64, backup_lba: u64, entries_lba: u64, entries_crc: u32) -> [u8; 512] {
+        let last_lba = self.size / SECTOR_SIZE - 1;
+        let mut header = [0; 512];
+        header[0..8].copy_from_slice(b"EFI PART");
+        header[8..12].copy_from_slice(&0x00010000u32.to_le_bytes());
+        header[12..16].copy_from_slice(&92u32.to_le_bytes());
+        header[24..32].copy_from_slice(&current_lba.to_le_bytes());
+        header[32..40].copy_from_slice(&backup_lba.to_le_bytes());
+        header[40..48].copy_from_slice(&(2 + ENTRY_SECTORS).to_le_bytes());
+        header[48..56].copy_from_slice(&(last_lba - ENTRY_SECTORS - 1).to_le_bytes());
+        header[56..72].copy_from_slice(&derive_guid("disk"));
+        header[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+        header[80..84].copy_from_slice(&(ENTRY_COUNT as u32).to_le_bytes());
+        header[84..88].copy_from_slice(&(ENTRY_SIZE as u32).to_le_bytes());
+        header[88..92].copy_from_slice(&entries_crc.to_le_bytes());
+        let header_crc = crc32fast::hash(&header[..92]);
+        header[16..20].copy_from_slice(&header_crc.to_le_bytes());
+        header
+    }
+
+    /// Create the sparse disk image file and write the partition tables. The file is truncated, so no data of a
+    /// previous image remains in the unused regions.
+    pub(crate) fn create<P: AsRef<Path>>(&self, image: P) -> Result<(), Error> {
+        let mut file = File::create(image)?;
+        file.set_len(self.size)?;
+        self.write(&mut file)
+    }
+
+    /// Write the protective MBR, the primary and the backup partition table into the disk image file
+    fn write(&self, file: &mut File) -> Result<(), Error> {
+        let last_lba = self.size / SECTOR_SIZE - 1;
+
+        // Protective MBR with a single partition of type 0xEE covering the disk
+        let mut mbr = [0; 512];
+        mbr[446 + 1..446 + 4].copy_from_slice(&[0x00, 0x02, 0x00]);
+        mbr[446 + 4] = 0xEE;
+        mbr[446 + 5..446 + 8].copy_from_slice(&[0xFF, 0xFF, 0xFF]);
+        mbr[446 + 8..446 + 12].copy_from_slice(&1u32.to_le_bytes());
+        mbr[446 + 12..446 + 16].copy_from_slice(&(last_lba.min(u32::MAX as u64) as u32).to_le_bytes());
+        mbr[510..512].copy_from_slice(&[0x55, 0xAA]);
+
+        // Partition entries with the name as UTF-16
+        let mut entries = vec![0; (ENTRY_COUNT * ENTRY_SIZE) as usize];
+        for (index, partition) in self.partitions.iter().enumerate() {
+            let entry = &mut entries[index * ENTRY_SIZE as usize..(index + 1) * ENTRY_SIZE as usize];
+            entry[0..16].copy_from_slice(&partition.type_guid);
+            entry[16..32].copy_from_slice(&derive_guid(&format!("partition-{}-{}", index, partition.name)));
+            entry[32..40].copy_from_slice(&partition.first_lba.to_le_bytes());
+            entry[40..48].copy_from_slice(&partition.last_lba.to_le_bytes());
+            for (character_index, character) in partition.name.encode_utf16().take(36).enumerate() {
+                entry[56 + character_index * 2..58 + character_index * 2].copy_from_slice(&character.to_le_bytes());
+            }
+        }
+
+        let entries_crc = crc32fast::hash(&entries);
+        let backup_entries_lba = last_lba - ENTRY_SECTORS;
+        let writes = [
+            (0, mbr.to_vec()),
+            (1, self.header(1, last_lba, 2, entries_crc).to_vec()),
+            (2, entries.clone()),
+            (backup_entries_lba, entries),
+            (last_lba, self.header(last_lba, 1, backup_entries_lba, entries_crc).to_vec())
+        ];
+        for (lba, data) in writes {
+            file.seek(SeekFrom::Start(lba * SECTOR_SIZE))?;
+            file.write_all(&data)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::image::gpt::{derive_guid, EFI_SYSTEM_PARTITION, GptDisk, LINUX_FILESYSTEM_PARTITION, parse_guid};
+
+    /// Bitwise CRC-32 (IEEE, reflected), independent of the table-driven implementation used by the writer
+    fn crc32(data: &[u8]) -> u32 {
+        !data.iter().fold(!0u32, |crc, byte| {
+            (0..8).fold(crc ^ *byte as u32, |crc, _| if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 })
+        })
+    }
+
+    fn read_u32(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn read_u64(data: &[u8], offset: usize) -> u64 {
+        u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+    }
+
+    /// Check the signature and CRC of the header and the CRC of its partition entries, then return the LBAs of the
+    /// header (current, backup and entries)
+    fn check_header(image: &[u8], lba: usize) -> (u64, u64, u64) {
+        let header = &image[lba * 512..lba * 512 + 92];
+        assert_eq!(&header[0..8], b"EFI PART");
+        let mut zeroed = header.to_vec();
+        zeroed[16..20].fill(0);
+        assert_eq!(read_u32(header, 16), crc32(&zeroed), "Header CRC of LBA {}", lba);
+
+        let entries_lba = read_u64(header, 72) as usize;
+        let entries = &image[entries_lba * 512..entries_lba * 512 + 128 * 128];
+        assert_eq!(read_u32(header, 88), crc32(entries), "Entries CRC of LBA {}", lba);
+        (read_u64(header, 24), read_u64(header, 32), entries_lba as u64)
+    }
+
+    #[test]
+    fn crc32_reference() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+        assert_eq!(crc32fast::hash(b"123456789"), 0xCBF43926);
+    }
+
+    #[test]
+    fn parse_mixed_endian_guid() {
+        assert_eq!(parse_guid(EFI_SYSTEM_PARTITION), Some([0x28, 0x73, 0x2A, 0xC1, 0x1F, 0xF8, 0xD2, 0x11, 0xBA, 0x4B,
+            0x00, 0xA0, 0xC9, 0x3E, 0xC9, 0x3B]));
+        assert_eq!(parse_guid("0fc63daf-8483-4772-8e79-3d69d8477de4"), parse_guid(LINUX_FILESYSTEM_PARTITION));
+    }
+
+    #[test]
+    fn parse_invalid_guid() {
+        for guid in ["", "C12A7328F81F11D2BA4B00A0C93EC93B", "C12A7328-F81F-11D2-BA4B-00A0C93EC93",
+                     "C12A7328-F81F-11D2",
+                     "C12A7328-F81F-11D2-BA4B-00A0C93EC93G", "C12A7328-F81F-11D2-BA4B-00A0C93EC93B-",
+                     "+12A7328-F81F-11D2-BA4B-00A0C93EC93B", "C12A732ü-F81F-11D2-BA4B-00A0C93EC93B"] {
+            assert_eq!(parse_guid(guid), None, "{}", guid);
+        }
+    }
+
+    #[test]
+    fn derived_guid_is_reproducible_version_4() {
+        let guid = derive_guid("disk");
+        assert_eq!(guid, derive_guid("disk"));
+        assert_ne!(guid, derive_guid("partition-0-EFI System Partition"));
+        assert_eq!(guid[7] >> 4, 4);
+        assert_eq!(guid[8] >> 6, 0b10);
+    }
+
+    #[test]
+    fn partitions_are_aligned() {
+        let disk = GptDisk::new(&[
+            (String::from("EFI System Partition"), parse_guid(EFI_SYSTEM_PARTITION).unwrap(), 40 * 1024 * 1024),
+            (String::from("root"), parse_guid(LINUX_FILESYSTEM_PARTITION).unwrap(), 1024 * 1024 + 1)
+        ]);
+        assert_eq!((disk.partitions[0].first_lba, disk.partitions[0].last_lba), (2048, 2048 + 81920 - 1));
+        assert_eq!(disk.partitions[0].offset(), 1024 * 1024);
+        assert_eq!(disk.partitions[1].first_lba, 2048 + 81920);
+        assert_eq!(disk.partitions[1].size(), 1024 * 1024 + 512);
+        assert_eq!(disk.size, (1 + 40 + 2) * 1024 * 1024 + 33 * 512);
+    }
+
+    #[test]
+    fn partition_tables_are_valid() {
+        let disk = GptDisk::new(&[
+            (String::from("EFI System Partition"), parse_guid(EFI_SYSTEM_PARTITION).unwrap(), 4 * 1024 * 1024),
+            (String::from("root"), parse_guid(LINUX_FILESYSTEM_PARTITION).unwrap(), 2 * 1024 * 1024)
+        ]);
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("disk.img");
+        disk.create(&path).unwrap();
+        let image = fs::read(&path).unwrap();
+        let last_lba = (image.len() / 512 - 1) as u64;
+        assert_eq!(image.len() as u64, disk.size);
+
+        // Protective MBR covering the disk
+        assert_eq!(&image[510..512], &[0x55, 0xAA]);
+        assert_eq!(image[446 + 4], 0xEE);
+        assert_eq!(read_u32(&image, 446 + 12) as u64, last_lba);
+
+        // Primary and backup header point to each other
+        assert_eq!(check_header(&image, 1), (1, last_lba, 2));
+        assert_eq!(check_header(&image, last_lba as usize), (last_lba, 1, last_lba - 32));
+        let backup_entries = (last_lba as usize - 32) * 512;
+        assert_eq!(image[1024..1024 + 128 * 128], image[backup_entries..backup_entries + 128 * 128]);
+
+        // Entry of the second partition with type GUID, LBAs and UTF-16 name
+        let entry = &image[1024 + 128..1024 + 256];
+        assert_eq!(&entry[0..16], &parse_guid(LINUX_FILESYSTEM_PARTITION).unwrap());
+        assert_eq!((read_u64(entry, 32), read_u64(entry, 40)), (2048 + 8192, 2048 + 8192 + 4095));
+        assert_eq!(&entry[56..66], &[b'r', 0, b'o', 0, b'o', 0, b't', 0, 0, 0]);
+    }
+}
@@ -0,0 +1,311 @@
+use std::fs::{metadata, read_dir, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use colorful::{Color, Colorful};
+use log::{debug, info};
+use serde::Deserialize;
+use crate::config::{PartitionConfig, VolumeConfig};
+use crate::error::Error;
+use crate::image::custom::CustomWriter;
+use crate::image::ext2::Ext2Writer;
+use crate::image::gpt::{BASIC_DATA_PARTITION, EFI_SYSTEM_PARTITION, GptDisk, GptPartition, LINUX_FILESYSTEM_PARTITION,
+                        derive_guid, parse_guid};
+use crate::tasks::cache::CacheManifest;
+use crate::utils::parse_size;
+
+pub(crate) mod custom;
+pub(crate) mod ext2;
+pub(crate) mod fat;
+pub(crate) mod gpt;
+
+pub(crate) use fat::FatWriter;
+
+/// The size of the buffer used to stream host files into the image
+pub(crate) const COPY_BUFFER_SIZE: usize = 256 * 1024;
+
+/// Writer of a file system into a region of the image file. Files and directories are written with the path in the
+/// file system and all data is written into the image file, when the writer is finished.
+pub(crate) trait FileSystemWriter {
+    fn name(&self) -> &'static str;
+
+    /// Create the directory and all parent directories
+    fn create_directory(&mut self, directory: &Path) -> Result<(), Error>;
+
+    /// Copy the host file into the file system, the parent directories are created if they don't exist
+    fn copy_into(&mut self, host_file: &Path, image_file: &Path) -> Result<(), Error>;
+
+    fn finish(self: Box<Self>) -> Result<(), Error>;
+}
+
+/// The file system of a partition
+#[derive(Deserialize, Clone, Copy, Default, Debug, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum FileSystemKind {
+    /// FAT32 file system, written with `fatfs`
+    #[default]
+    Fat,
+    /// ext2 file system (revision 1 with 128-byte inodes)
+    Ext2,
+    /// File system generated by a custom command template from the staged files
+    Custom
+}
+
+impl FileSystemKind {
+    /// The partition type GUID used, if the partition doesn't specify the type
+    fn default_type_guid(&self) -> &'static str {
+        match self {
+            FileSystemKind::Fat => BASIC_DATA_PARTITION,
+            FileSystemKind::Ext2 | FileSystemKind::Custom => LINUX_FILESYSTEM_PARTITION
+        }
+    }
+}
+
+/// Check whether the existing file can be overwritten by the generated image or ISO file. Files are only overwritten
+/// without force, if the specified cache manifest of a previous build exists or the file carries the identification
+/// written by OSImage: the disk GUID of the GPT disk, the configured label and ID of the FAT volume or the configured
+/// volume ID of the ISO file. Files only recognized by the identification must also have the expected size
+/// (`block_size * block_count` or the size of the GPT disk), while files with cache manifest are recreated with the
+/// expected size.
+pub(crate) fn check_overwrite<P: AsRef<Path>, M: AsRef<Path>>(path: P, manifest: M, expected_size: Option<u64>,
+                                                              volume: &VolumeConfig,
+                                                              force: bool) -> Result<(), Error> {
+    let path = path.as_ref();
+    let path_name = path.to_str().unwrap().to_owned();
+    let Ok(metadata) = metadata(path) else {
+        return Ok(());
+    };
+
+    if !metadata.is_file() {
+        return Err(Error::OverwriteRefused(path_name, String::from("Path is no file")));
+    }
+
+    if force {
+        debug!("Overwrite {} (forced)", path_name);
+        return Ok(());
+    }
+
+    let mut header = Vec::new();
+    File::open(path)?.take(0x8048).read_to_end(&mut header)?;
+    let label: [u8; 11] = volume.label.as_deref().map_or(*b"NO NAME    ", fat::padded_name);
+    let volume_id = volume.id.unwrap_or(0x12345678).to_le_bytes();
+    let fat_volume = header.get(510..512) == Some(&[0x55, 0xAA]) && header.get(82..90) == Some(b"FAT32   ")
+        && header.get(67..71) == Some(&volume_id) && header.get(71..82) == Some(&label);
+    let gpt_disk = header.get(512..520) == Some(b"EFI PART") && header.get(568..584) == Some(&derive_guid("disk"));
+    let iso_volume_id: [u8; 32] = fat::padded_name(volume.iso_volume_id.as_deref().unwrap_or("EFI_ISO_BOOT"));
+    let iso_file = header.get(0x8001..0x8006) == Some(b"CD001")
+        && header.get(0x8028..0x8048).is_some_and(|volume_id| volume_id.eq_ignore_ascii_case(&iso_volume_id));
+    let cached = CacheManifest::read(manifest).is_some();
+    if !header.is_empty() && !cached {
+        if !fat_volume && !gpt_disk && !iso_file {
+            return Err(Error::OverwriteRefused(path_name, String::from("File doesn't look like an image generated \
+                by OSImage, use --force to overwrite it")));
+        }
+
+        if let Some(expected_size) = expected_size.filter(|size| *size != metadata.len()) {
+            return Err(Error::OverwriteRefused(path_name, format!("File has {} bytes instead of the {} bytes of the \
+                image, use --force to recreate it", metadata.len(), expected_size)));
+        }
+    }
+
+    if let Some(expected_size) = expected_size.filter(|size| *size != metadata.len()) {
+        info!("Recreate {} with {} bytes instead of {} bytes", path_name, expected_size, metadata.len());
+    }
+    Ok(())
+}
+
+/// Check whether the existing directory can be replaced by the generated directory. Non-empty directories are only
+/// replaced without force, if the cache manifest of a previous build is beside the directory.
+pub(crate) fn check_overwrite_directory<P: AsRef<Path>>(path: P, force: bool) -> Result<(), Error> {
+    let path = path.as_ref();
+    let path_name = path.to_str().unwrap().to_owned();
+    let Ok(metadata) = metadata(path) else {
+        return Ok(());
+    };
+
+    if !metadata.is_dir() {
+        return Err(Error::OverwriteRefused(path_name, String::from("Path is no directory")));
+    }
+
+    if force {
+        debug!("Overwrite {} (forced)", path_name);
+        return Ok(());
+    }
+
+    if read_dir(path)?.next().is_some() && CacheManifest::read(CacheManifest::path(path)).is_none() {
+        return Err(Error::OverwriteRefused(path_name, String::from("Directory isn't empty and wasn't generated by \
+            OSImage, use --force to overwrite it")));
+    }
+    Ok(())
+}
+
+/// Place the EFI system partition with the specified size and the configured partitions in the GUID partition table.
+/// The EFI system partition is the first partition.
+pub(crate) fn partition_table(esp_size: u64, partitions: &[PartitionConfig]) -> Result<GptDisk, Error> {
+    let mut layout = vec![(String::from("EFI System Partition"), parse_guid(EFI_SYSTEM_PARTITION).unwrap(), esp_size)];
+    for partition in partitions {
+        let type_guid = partition.type_guid.as_deref().unwrap_or(partition.filesystem.default_type_guid());
+        let type_guid = parse_guid(type_guid)
+            .ok_or(Error::InvalidConfig(format!("Invalid type GUID '{}' of '{}'", type_guid, partition.name)))?;
+        let size = parse_size(&partition.size).map_err(Error::InvalidConfig)?;
+        layout.push((partition.name.clone(), type_guid, size));
+    }
+    Ok(GptDisk::new(&layout))
+}
+
+/// Collect the host files of the partition with their path in the file system. The files of the directory are
+/// collected recursively and the explicitly listed files are added afterwards.
+pub(crate) fn partition_files<P: AsRef<Path>>(workspace: P,
+                                              partition: &PartitionConfig) -> Result<Vec<(PathBuf, String)>, Error> {
+    fn collect(directory: &Path, prefix: &str, files: &mut Vec<(PathBuf, String)>) -> Result<(), Error> {
+        let mut entries = read_dir(directory)?.collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let name = format!("{}{}", prefix, entry.file_name().to_str().unwrap());
+            if entry.file_type()?.is_dir() {
+                collect(&entry.path(), &format!("{}/", name), files)?;
+            } else {
+                files.push((entry.path(), name));
+            }
+        }
+        Ok(())
+    }
+
+    let workspace = workspace.as_ref();
+    let mut files = Vec::new();
+    if let Some(directory) = partition.directory.as_ref() {
+        let directory = workspace.join(directory);
+        if !directory.is_dir() {
+            return Err(Error::FileNotFound(directory.to_str().unwrap().to_owned()));
+        }
+        collect(&directory, "", &mut files)?;
+    }
+
+    for (image_file, host_file) in &partition.files {
+        files.push((workspace.join(host_file), image_file.trim_start_matches('/').to_owned()));
+    }
+    Ok(files)
+}
+
+/// Write the files into the file system of the partition in the image file
+pub(crate) fn write_partition<P: AsRef<Path>>(workspace: P, image: &Path, partition: &PartitionConfig,
+                                              region: &GptPartition, files: &[(PathBuf, String)],
+                                              keep_intermediate: bool) -> Result<(), Error> {
+    let mut writer: Box<dyn FileSystemWriter> = match partition.filesystem {
+        FileSystemKind::Fat => {
+            let volume = VolumeConfig { label: partition.label.clone(), ..Default::default() };
+            let block_count = (region.size() / gpt::SECTOR_SIZE) as u32;
+            Box::new(FatWriter::new(image, region.offset(), gpt::SECTOR_SIZE as u16, block_count, &volume)?)
+        }
+        FileSystemKind::Ext2 => Box::new(Ext2Writer::new(image, region.offset(), region.size(), partition)?),
+        FileSystemKind::Custom => {
+            Box::new(CustomWriter::new(workspace, image, region.offset(), region.size(), partition,
+                keep_intermediate)?)
+        }
+    };
+
+    info!("Write {} partition {}", writer.name(), partition.name.clone().color(Color::Green));
+    for (host_file, image_file) in files {
+        writer.copy_into(host_file, Path::new(image_file))?;
+    }
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::config::VolumeConfig;
+    use crate::error::Error;
+    use crate::image::{check_overwrite, check_overwrite_directory, FatWriter, FileSystemWriter, partition_table};
+    use crate::image::fat::MIN_FAT32_SECTORS;
+    use crate::tasks::cache::CacheManifest;
+
+    #[test]
+    fn overwrite_foreign_files_with_force() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("image.img");
+        let manifest = CacheManifest::path(&path);
+        let volume = VolumeConfig::default();
+        assert!(check_overwrite(&path, &manifest, None, &volume, false).is_ok());
+
+        fs::write(&path, []).unwrap();
+        assert!(check_overwrite(&path, &manifest, Some(1024), &volume, false).is_ok());
+
+        fs::write(&path, b"important data").unwrap();
+        assert!(check_overwrite(&path, &manifest, None, &volume, false).is_err());
+        assert!(check_overwrite(&path, &manifest, None, &volume, true).is_ok());
+        assert!(check_overwrite(directory.path(), &manifest, None, &volume, true).is_err());
+
+        // Files with cache manifest are recreated with the expected size
+        CacheManifest::new(&[]).write(&manifest).unwrap();
+        assert!(check_overwrite(&path, &manifest, Some(1024), &volume, false).is_ok());
+    }
+
+    #[test]
+    fn overwrite_fat_volume_with_configured_identification() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("image.img");
+        let manifest = CacheManifest::path(&path);
+        let size = Some(MIN_FAT32_SECTORS as u64 * 512);
+        let volume = VolumeConfig { label: Some(String::from("osimage")), id: Some(0xCAFE), ..VolumeConfig::default() };
+        Box::new(FatWriter::new(&path, 0, 512, MIN_FAT32_SECTORS, &volume).unwrap()).finish().unwrap();
+        assert!(check_overwrite(&path, &manifest, size, &volume, false).is_ok());
+        let other_label = VolumeConfig { id: Some(0xCAFE), ..VolumeConfig::default() };
+        assert!(check_overwrite(&path, &manifest, size, &other_label, false).is_err());
+        let other_id = VolumeConfig { id: None, ..volume.clone() };
+        assert!(check_overwrite(&path, &manifest, size, &other_id, false).is_err());
+
+        // The identification of the intermediate image is ignored, if the manifest of the ISO file exists
+        let iso_manifest = CacheManifest::path(directory.path().join("image.iso"));
+        CacheManifest::new(&[]).write(&iso_manifest).unwrap();
+        assert!(check_overwrite(&path, &iso_manifest, size, &other_id, false).is_ok());
+    }
+
+    #[test]
+    fn overwrite_only_images_with_expected_size() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("image.img");
+        let manifest = CacheManifest::path(&path);
+        let volume = VolumeConfig::default();
+        Box::new(FatWriter::new(&path, 0, 512, MIN_FAT32_SECTORS, &volume).unwrap()).finish().unwrap();
+        let error = check_overwrite(&path, &manifest, Some(MIN_FAT32_SECTORS as u64 * 1024), &volume, false);
+        assert!(matches!(error, Err(Error::OverwriteRefused(_, message)) if message == format!("File has {} bytes \
+            instead of the {} bytes of the image, use --force to recreate it", MIN_FAT32_SECTORS as u64 * 512,
+            MIN_FAT32_SECTORS as u64 * 1024)));
+        assert!(check_overwrite(&path, &manifest, Some(MIN_FAT32_SECTORS as u64 * 1024), &volume, true).is_ok());
+        assert!(check_overwrite(&path, &manifest, None, &volume, false).is_ok());
+    }
+
+    #[test]
+    fn overwrite_gpt_disk_with_disk_guid() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("disk.img");
+        let manifest = CacheManifest::path(&path);
+        let volume = VolumeConfig::default();
+        let disk = partition_table(1024 * 1024, &[]).unwrap();
+        disk.create(&path).unwrap();
+        assert!(check_overwrite(&path, &manifest, Some(disk.size), &volume, false).is_ok());
+
+        let mut image = fs::read(&path).unwrap();
+        image[568] ^= 0xFF;
+        fs::write(&path, image).unwrap();
+        assert!(check_overwrite(&path, &manifest, Some(disk.size), &volume, false).is_err());
+    }
+
+    #[test]
+    fn overwrite_foreign_directories_with_force() {
+        let directory = tempfile::tempdir().unwrap();
+        let esp = directory.path().join("esp");
+        assert!(check_overwrite_directory(&esp, false).is_ok());
+
+        fs::create_dir(&esp).unwrap();
+        assert!(check_overwrite_directory(&esp, false).is_ok());
+
+        fs::write(esp.join("notes.txt"), b"important data").unwrap();
+        assert!(check_overwrite_directory(&esp, false).is_err());
+        assert!(check_overwrite_directory(&esp, true).is_ok());
+        assert!(check_overwrite_directory(esp.join("notes.txt"), true).is_err());
+
+        CacheManifest::new(&[]).write(CacheManifest::path(&esp)).unwrap();
+        assert!(check_overwrite_directory(&esp, false).is_ok());
+    }
+}
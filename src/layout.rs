@@ -0,0 +1,530 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use colorful::{Color, Colorful};
+use log::debug;
+use crate::config::LayoutEntry;
+use crate::error::Error;
+
+const ENTRY_SIZE: usize = 32;
+const ATTRIBUTE_READ_ONLY: u8 = 0x01;
+const ATTRIBUTE_HIDDEN: u8 = 0x02;
+const ATTRIBUTE_SYSTEM: u8 = 0x04;
+const ATTRIBUTE_VOLUME_ID: u8 = 0x08;
+const ATTRIBUTE_DIRECTORY: u8 = 0x10;
+const ATTRIBUTE_LONG_NAME: u8 = 0x0F;
+const DELETED_ENTRY: u8 = 0xE5;
+const END_OF_CHAIN: u32 = 0x0FFFFFF8;
+
+/// The characters allowed in 8.3 short names besides letters and digits
+const SHORT_NAME_SPECIAL_CHARACTERS: &str = "!#$%&'()-@^_`{}~";
+
+/// A date and time in the FAT format (date and time fields of the directory entry)
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct FatTimestamp {
+    date: u16,
+    time: u16
+}
+
+impl FatTimestamp {
+    /// Parse the timestamp in the format `YYYY-MM-DD HH:MM:SS` (or with `T` as separator). The seconds are rounded
+    /// down to even seconds, because FAT stores the time with two seconds resolution.
+    fn parse(value: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid timestamp '{}', expected YYYY-MM-DD HH:MM:SS", value);
+        let (date, time) = value.split_once([' ', 'T']).ok_or_else(invalid)?;
+        let parse_fields = |value: &str, separator: char| -> Result<Vec<u16>, String> {
+            value.split(separator).map(|field| field.parse::<u16>().map_err(|_| invalid())).collect()
+        };
+
+        let (date, time) = (parse_fields(date, '-')?, parse_fields(time, ':')?);
+        let (&[year, month, day], &[hour, minute, second]) = (date.as_slice(), time.as_slice()) else {
+            return Err(invalid());
+        };
+
+        if !(1980..=2107).contains(&year) || !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23
+            || minute > 59 || second > 59 {
+            return Err(format!("Timestamp '{}' is out of the range of FAT (1980-2107)", value));
+        }
+
+        let leap_year = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+        let days = match month {
+            2 if leap_year => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31
+        };
+        if day > days {
+            return Err(format!("Timestamp '{}' has no valid date, the month has {} days", value, days));
+        }
+
+        Ok(FatTimestamp {
+            date: ((year - 1980) << 9) | (month << 5) | day,
+            time: (hour << 11) | (minute << 5) | (second / 2)
+        })
+    }
+}
+
+/// Convert the 8.3 short name (e.g. `KERNEL.ELF`) into the 11 bytes of the directory entry
+fn encode_short_name(name: &str) -> Result<[u8; 11], String> {
+    let (base, extension) = name.split_once('.').unwrap_or((name, ""));
+    if base.is_empty() || base.len() > 8 || extension.len() > 3 {
+        return Err(format!("'{}' is no valid 8.3 short name", name));
+    }
+
+    let mut encoded = [b' '; 11];
+    for (index, character) in base.bytes().chain(std::iter::repeat(b' ').take(8 - base.len())).chain(extension.bytes())
+        .enumerate() {
+        let character = character.to_ascii_uppercase();
+        if character != b' ' && !character.is_ascii_alphanumeric()
+            && !SHORT_NAME_SPECIAL_CHARACTERS.as_bytes().contains(&character) {
+            return Err(format!("Short name '{}' contains the invalid character '{}'", name, character as char));
+        }
+        encoded[index] = character;
+    }
+    Ok(encoded)
+}
+
+/// Convert the 11 bytes of the directory entry into the short name (e.g. `KERNEL.ELF`)
+fn decode_short_name(name: &[u8]) -> String {
+    let mut base = name[..8].to_vec();
+    if base[0] == 0x05 {
+        base[0] = DELETED_ENTRY;
+    }
+
+    let base = String::from_utf8_lossy(&base).trim_end().to_owned();
+    let extension = String::from_utf8_lossy(&name[8..11]).trim_end().to_owned();
+    match extension.is_empty() {
+        true => base,
+        false => format!("{}.{}", base, extension)
+    }
+}
+
+/// The checksum of the short name, stored in the long name entries of the entry
+fn short_name_checksum(name: &[u8]) -> u8 {
+    name.iter().fold(0u8, |sum, byte| ((sum & 1) << 7).wrapping_add(sum >> 1).wrapping_add(*byte))
+}
+
+/// A directory of the FAT volume, read into memory with the clusters it's stored in
+struct Directory {
+    clusters: Vec<u32>,
+    data: Vec<u8>
+}
+
+/// A short entry of a directory with the indices of the long name entries preceding it
+struct DirectoryEntry {
+    index: usize,
+    long_name_indices: Vec<usize>,
+    short_name: String,
+    long_name: Option<String>
+}
+
+impl DirectoryEntry {
+    fn matches(&self, name: &str) -> bool {
+        self.short_name.eq_ignore_ascii_case(name)
+            || self.long_name.as_ref().is_some_and(|long_name| long_name.eq_ignore_ascii_case(name))
+    }
+}
+
+impl Directory {
+    fn entry(&self, index: usize) -> &[u8] {
+        &self.data[index * ENTRY_SIZE..(index + 1) * ENTRY_SIZE]
+    }
+
+    fn entry_mut(&mut self, index: usize) -> &mut [u8] {
+        &mut self.data[index * ENTRY_SIZE..(index + 1) * ENTRY_SIZE]
+    }
+
+    /// Return all short entries of the directory except the volume label and the deleted entries
+    fn entries(&self) -> Vec<DirectoryEntry> {
+        let mut entries = Vec::new();
+        let mut long_name_indices = Vec::new();
+        let mut long_name: Vec<u16> = Vec::new();
+        for index in 0..self.data.len() / ENTRY_SIZE {
+            let entry = self.entry(index);
+            match (entry[0], entry[11]) {
+                (0x00, _) => break,
+                (DELETED_ENTRY, _) => {
+                    long_name_indices.clear();
+                    long_name.clear();
+                }
+                (_, ATTRIBUTE_LONG_NAME) => {
+                    // Long name entries are stored in reverse order before the short entry
+                    let characters = entry[1..11].chunks(2)
+                        .chain(entry[14..26].chunks(2))
+                        .chain(entry[28..32].chunks(2))
+                        .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+                        .take_while(|character| *character != 0x0000 && *character != 0xFFFF)
+                        .collect::<Vec<_>>();
+                    long_name.splice(0..0, characters);
+                    long_name_indices.push(index);
+                }
+                (_, attributes) if attributes & ATTRIBUTE_VOLUME_ID != 0 => {
+                    long_name_indices.clear();
+                    long_name.clear();
+                }
+                _ => {
+                    let checksum = short_name_checksum(&entry[..11]);
+                    let valid_long_name = !long_name_indices.is_empty()
+                        && long_name_indices.iter().all(|index| self.entry(*index)[13] == checksum);
+                    entries.push(DirectoryEntry {
+                        index,
+                        long_name_indices: if valid_long_name { long_name_indices.clone() } else { Vec::new() },
+                        short_name: decode_short_name(&entry[..11]),
+                        long_name: valid_long_name.then(|| String::from_utf16_lossy(&long_name))
+                    });
+                    long_name_indices.clear();
+                    long_name.clear();
+                }
+            }
+        }
+        entries
+    }
+}
+
+/// A FAT32 volume in the image file, accessed directly to edit the directory entries
+struct FatVolume {
+    file: File,
+    cluster_size: u64,
+    fat_offset: u64,
+    data_offset: u64,
+    root_cluster: u32
+}
+
+impl FatVolume {
+    /// Open the volume at the offset of the image file (the start of the file or the EFI system partition)
+    fn open<P: AsRef<Path>>(path: P, offset: u64) -> Result<Self, String> {
+        let mut file = OpenOptions::new().read(true).write(true).open(path).map_err(|error| error.to_string())?;
+        let mut boot_sector = [0; 512];
+        file.seek(SeekFrom::Start(offset)).and_then(|_| file.read_exact(&mut boot_sector))
+            .map_err(|error| error.to_string())?;
+
+        let read_u16 = |offset: usize| u16::from_le_bytes([boot_sector[offset], boot_sector[offset + 1]]) as u64;
+        let read_u32 = |offset: usize| u32::from_le_bytes(boot_sector[offset..offset + 4].try_into().unwrap());
+        let bytes_per_sector = read_u16(11);
+        let sectors_per_cluster = boot_sector[13] as u64;
+        let reserved_sectors = read_u16(14);
+        let fats = boot_sector[16] as u64;
+        if read_u16(22) != 0 || read_u16(17) != 0 || bytes_per_sector == 0 || sectors_per_cluster == 0 {
+            return Err(String::from("Image is no FAT32 volume"));
+        }
+
+        Ok(FatVolume {
+            file,
+            cluster_size: bytes_per_sector * sectors_per_cluster,
+            fat_offset: offset + reserved_sectors * bytes_per_sector,
+            data_offset: offset + (reserved_sectors + fats * read_u32(36) as u64) * bytes_per_sector,
+            root_cluster: read_u32(44)
+        })
+    }
+
+    fn cluster_offset(&self, cluster: u32) -> u64 {
+        self.data_offset + (cluster as u64 - 2) * self.cluster_size
+    }
+
+    fn read_directory(&mut self, first_cluster: u32) -> Result<Directory, String> {
+        let mut directory = Directory { clusters: Vec::new(), data: Vec::new() };
+        let mut cluster = first_cluster;
+        while (2..END_OF_CHAIN).contains(&cluster) {
+            if directory.clusters.contains(&cluster) {
+                return Err(format!("Cluster chain of directory at cluster {} is cyclic", first_cluster));
+            }
+
+            let mut data = vec![0; self.cluster_size as usize];
+            let mut next = [0; 4];
+            (|| {
+                self.file.seek(SeekFrom::Start(self.cluster_offset(cluster)))?;
+                self.file.read_exact(&mut data)?;
+                self.file.seek(SeekFrom::Start(self.fat_offset + cluster as u64 * 4))?;
+                self.file.read_exact(&mut next)
+            })().map_err(|error| error.to_string())?;
+
+            directory.clusters.push(cluster);
+            directory.data.extend(data);
+            cluster = u32::from_le_bytes(next) & 0x0FFFFFFF;
+        }
+        Ok(directory)
+    }
+
+    fn write_directory(&mut self, directory: &Directory) -> Result<(), String> {
+        for (cluster, data) in directory.clusters.iter().zip(directory.data.chunks(self.cluster_size as usize)) {
+            self.file.seek(SeekFrom::Start(self.cluster_offset(*cluster))).map_err(|error| error.to_string())?;
+            self.file.write_all(data).map_err(|error| error.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// Find the directory containing the entry of the path and return the directory with the entry
+    fn find(&mut self, path: &str) -> Result<(Directory, DirectoryEntry), String> {
+        let components: Vec<&str> = path.split(['/', '\\']).filter(|component| !component.is_empty()).collect();
+        let Some((name, parents)) = components.split_last() else {
+            return Err(String::from("The root directory has no entry"));
+        };
+
+        let mut directory = self.read_directory(self.root_cluster)?;
+        for parent in parents {
+            let entry = directory.entries().into_iter().find(|entry| entry.matches(parent))
+                .filter(|entry| directory.entry(entry.index)[11] & ATTRIBUTE_DIRECTORY != 0)
+                .ok_or(format!("Directory '{}' not found", parent))?;
+            let raw_entry = directory.entry(entry.index);
+            let cluster = u32::from_le_bytes([raw_entry[26], raw_entry[27], raw_entry[20], raw_entry[21]]);
+            directory = self.read_directory(cluster)?;
+        }
+
+        let entry = directory.entries().into_iter().find(|entry| entry.matches(name))
+            .ok_or(format!("Entry '{}' not found", name))?;
+        Ok((directory, entry))
+    }
+
+    /// Apply the attributes, timestamps and names of the layout entry to the directory entry of the path
+    fn apply(&mut self, layout: &LayoutEntry) -> Result<(), String> {
+        let (mut directory, entry) = self.find(&layout.path)?;
+        // Set or clear the specified attributes, the other attributes are kept
+        let mut attributes = directory.entry(entry.index)[11];
+        for (enabled, attribute) in [(layout.read_only, ATTRIBUTE_READ_ONLY), (layout.hidden, ATTRIBUTE_HIDDEN),
+                                     (layout.system, ATTRIBUTE_SYSTEM)] {
+            match enabled {
+                Some(true) => attributes |= attribute,
+                Some(false) => attributes &= !attribute,
+                None => {}
+            }
+        }
+        directory.entry_mut(entry.index)[11] = attributes;
+
+        // Write timestamps (created at 13-17, accessed at 18-19, modified at 22-25)
+        if let Some(created) = layout.created.as_ref() {
+            let timestamp = FatTimestamp::parse(created)?;
+            let raw_entry = directory.entry_mut(entry.index);
+            raw_entry[13] = 0;
+            raw_entry[14..16].copy_from_slice(&timestamp.time.to_le_bytes());
+            raw_entry[16..18].copy_from_slice(&timestamp.date.to_le_bytes());
+        }
+
+        if let Some(accessed) = layout.accessed.as_ref() {
+            let timestamp = FatTimestamp::parse(accessed)?;
+            directory.entry_mut(entry.index)[18..20].copy_from_slice(&timestamp.date.to_le_bytes());
+        }
+
+        if let Some(modified) = layout.modified.as_ref() {
+            let timestamp = FatTimestamp::parse(modified)?;
+            let raw_entry = directory.entry_mut(entry.index);
+            raw_entry[22..24].copy_from_slice(&timestamp.time.to_le_bytes());
+            raw_entry[24..26].copy_from_slice(&timestamp.date.to_le_bytes());
+        }
+
+        // Replace the short name and update the checksum of the long name entries
+        if let Some(short_name) = layout.short_name.as_ref() {
+            let encoded = encode_short_name(short_name)?;
+            if directory.entries().iter().any(|other| other.index != entry.index
+                && other.short_name.eq_ignore_ascii_case(&decode_short_name(&encoded))) {
+                return Err(format!("Short name '{}' is already used in the directory", short_name));
+            }
+
+            let raw_entry = directory.entry_mut(entry.index);
+            raw_entry[..11].copy_from_slice(&encoded);
+            raw_entry[12] = 0;
+            let checksum = short_name_checksum(&encoded);
+            entry.long_name_indices.iter().for_each(|index| directory.entry_mut(*index)[13] = checksum);
+        }
+
+        // Remove the long name by deleting the long name entries
+        if layout.long_name == Some(false) {
+            entry.long_name_indices.iter().for_each(|index| directory.entry_mut(*index)[0] = DELETED_ENTRY);
+        }
+        self.write_directory(&directory)
+    }
+}
+
+/// Apply the layout entries to the FAT volume at the offset of the image file. The image must be unmounted, because
+/// the directory entries are edited directly in the file.
+pub(crate) fn apply_layout<P: AsRef<Path>>(image: P, offset: u64, entries: &[LayoutEntry]) -> Result<(), Error> {
+    let image_name = image.as_ref().to_string_lossy().into_owned();
+    let mut volume = FatVolume::open(&image, offset).map_err(|error| Error::LayoutError(image_name, error))?;
+    for entry in entries {
+        debug!("Apply layout to {}", entry.path.clone().gradient(Color::Red));
+        volume.apply(entry).map_err(|error| Error::LayoutError(entry.path.clone(), error))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use crate::config::{LayoutEntry, VolumeConfig};
+    use crate::error::Error;
+    use crate::image::{FatWriter, FileSystemWriter};
+    use crate::layout::{apply_layout, ATTRIBUTE_HIDDEN, ATTRIBUTE_READ_ONLY, ATTRIBUTE_SYSTEM, decode_short_name,
+                        DELETED_ENTRY, encode_short_name, FatTimestamp, FatVolume};
+
+    /// Create a FAT32 image with the specified files in the root directory
+    fn image(directory: &Path, files: &[&str]) -> PathBuf {
+        let image = directory.join("image.img");
+        let mut writer = Box::new(FatWriter::new(&image, 0, 512, 70000, &VolumeConfig::default()).unwrap());
+        for file in files {
+            let host_file = directory.join(file);
+            fs::write(&host_file, file.as_bytes()).unwrap();
+            writer.copy_into(&host_file, Path::new(file)).unwrap();
+        }
+        writer.finish().unwrap();
+        image
+    }
+
+    #[test]
+    fn parse_timestamp() {
+        let timestamp = FatTimestamp::parse("2024-02-29 13:37:59").unwrap();
+        assert_eq!(timestamp, FatTimestamp { date: (44 << 9) | (2 << 5) | 29, time: (13 << 11) | (37 << 5) | 29 });
+        assert_eq!(FatTimestamp::parse("2024-02-29T13:37:58"), Ok(timestamp));
+        assert_eq!(FatTimestamp::parse("1980-01-01 00:00:00"), Ok(FatTimestamp { date: (1 << 5) | 1, time: 0 }));
+        assert_eq!(FatTimestamp::parse("2107-12-31 23:59:59").unwrap().date, (127 << 9) | (12 << 5) | 31);
+    }
+
+    #[test]
+    fn parse_timestamp_out_of_range() {
+        for value in ["1979-12-31 23:59:59", "2108-01-01 00:00:00", "2024-13-01 00:00:00", "2024-01-00 00:00:00",
+                      "2024-01-01 24:00:00", "2024-01-01 00:60:00", "2024-01-01 00:00:60"] {
+            assert_eq!(FatTimestamp::parse(value),
+                Err(format!("Timestamp '{}' is out of the range of FAT (1980-2107)", value)));
+        }
+    }
+
+    #[test]
+    fn parse_timestamp_with_invalid_day() {
+        for (value, days) in [("2024-02-30 00:00:00", 29), ("2023-02-29 00:00:00", 28), ("2100-02-29 00:00:00", 28),
+                              ("2024-04-31 00:00:00", 30), ("2024-11-31 00:00:00", 30)] {
+            assert_eq!(FatTimestamp::parse(value),
+                Err(format!("Timestamp '{}' has no valid date, the month has {} days", value, days)));
+        }
+        assert!(FatTimestamp::parse("2000-02-29 00:00:00").is_ok());
+        assert!(FatTimestamp::parse("2024-12-31 00:00:00").is_ok());
+    }
+
+    #[test]
+    fn parse_invalid_timestamp() {
+        for value in ["2024-01-01", "2024/01/01 00:00:00", "2024-01-01 00:00", "2024-01-01-01 00:00:00",
+                      "2024-01-01 0a:00:00", "-2024-01-01 00:00:00", ""] {
+            assert_eq!(FatTimestamp::parse(value),
+                Err(format!("Invalid timestamp '{}', expected YYYY-MM-DD HH:MM:SS", value)));
+        }
+    }
+
+    #[test]
+    fn encode_valid_short_names() {
+        assert_eq!(&encode_short_name("kernel.elf").unwrap(), b"KERNEL  ELF");
+        assert_eq!(&encode_short_name("README").unwrap(), b"README     ");
+        assert_eq!(&encode_short_name("BOOTX64.EFI").unwrap(), b"BOOTX64 EFI");
+        assert_eq!(&encode_short_name("KERNEL~1.E").unwrap(), b"KERNEL~1E  ");
+        assert_eq!(&encode_short_name("{A}$#!.@^_").unwrap(), b"{A}$#!  @^_");
+        assert_eq!(decode_short_name(&encode_short_name("kernel.elf").unwrap()), "KERNEL.ELF");
+        assert_eq!(decode_short_name(&encode_short_name("readme").unwrap()), "README");
+    }
+
+    #[test]
+    fn encode_invalid_short_names() {
+        for name in ["", ".ELF", "KERNELELF.BIN", "KERNEL.TEXT"] {
+            assert_eq!(encode_short_name(name), Err(format!("'{}' is no valid 8.3 short name", name)));
+        }
+        assert_eq!(encode_short_name("A+B.TXT"),
+            Err(String::from("Short name 'A+B.TXT' contains the invalid character '+'")));
+        assert_eq!(encode_short_name("A.B.C"),
+            Err(String::from("Short name 'A.B.C' contains the invalid character '.'")));
+        assert_eq!(encode_short_name("KÉRNEL").map_err(|error| error.contains("invalid character")), Err(true));
+    }
+
+    #[test]
+    fn short_name_collisions_are_rejected() {
+        let directory = tempfile::tempdir().unwrap();
+        let image = image(directory.path(), &["kernel.elf", "other.elf"]);
+        let entry = |path: &str, short_name: &str| LayoutEntry {
+            path: path.to_owned(),
+            short_name: Some(short_name.to_owned()),
+            ..LayoutEntry::default()
+        };
+
+        let error = apply_layout(&image, 0, &[entry("other.elf", "kernel.elf")]).unwrap_err();
+        assert!(matches!(error, Error::LayoutError(path, message) if path == "other.elf"
+            && message == "Short name 'kernel.elf' is already used in the directory"));
+
+        // The entry keeps its long name, so it's still found by the long name and by the new short name
+        apply_layout(&image, 0, &[entry("other.elf", "OTHER~2.ELF")]).unwrap();
+        let mut volume = FatVolume::open(&image, 0).unwrap();
+        let (_, renamed) = volume.find("OTHER~2.ELF").unwrap();
+        assert_eq!(renamed.long_name.as_deref(), Some("other.elf"));
+        apply_layout(&image, 0, &[entry("other.elf", "OTHER.ELF")]).unwrap();
+        assert_eq!(volume.find("other.elf").unwrap().1.short_name, "OTHER.ELF");
+    }
+
+    /// Return the raw directory entry of the path
+    fn raw_entry(image: &Path, path: &str) -> Vec<u8> {
+        let (directory, entry) = FatVolume::open(image, 0).unwrap().find(path).unwrap();
+        directory.entry(entry.index).to_vec()
+    }
+
+    #[test]
+    fn attributes_are_set_and_cleared() {
+        let directory = tempfile::tempdir().unwrap();
+        let image = image(directory.path(), &["kernel.elf"]);
+        let original = raw_entry(&image, "kernel.elf")[11];
+        assert_eq!(original & (ATTRIBUTE_READ_ONLY | ATTRIBUTE_HIDDEN | ATTRIBUTE_SYSTEM), 0);
+
+        let entry = |read_only, hidden, system| LayoutEntry {
+            path: String::from("kernel.elf"),
+            read_only,
+            hidden,
+            system,
+            ..LayoutEntry::default()
+        };
+        apply_layout(&image, 0, &[entry(Some(true), None, None)]).unwrap();
+        assert_eq!(raw_entry(&image, "kernel.elf")[11], original | 0x01);
+        apply_layout(&image, 0, &[entry(None, Some(true), Some(true))]).unwrap();
+        assert_eq!(raw_entry(&image, "kernel.elf")[11], original | 0x07);
+        apply_layout(&image, 0, &[entry(Some(false), None, Some(false))]).unwrap();
+        assert_eq!(raw_entry(&image, "kernel.elf")[11], original | 0x02);
+        apply_layout(&image, 0, &[entry(None, Some(false), None)]).unwrap();
+        assert_eq!(raw_entry(&image, "kernel.elf")[11], original);
+    }
+
+    #[test]
+    fn timestamps_are_written_into_their_fields() {
+        let directory = tempfile::tempdir().unwrap();
+        let image = image(directory.path(), &["kernel.elf"]);
+        let original = raw_entry(&image, "kernel.elf");
+        apply_layout(&image, 0, &[LayoutEntry {
+            path: String::from("kernel.elf"),
+            created: Some(String::from("2001-02-03 04:05:06")),
+            accessed: Some(String::from("2002-03-04 05:06:07")),
+            modified: Some(String::from("2003-04-05 06:07:08")),
+            ..LayoutEntry::default()
+        }]).unwrap();
+
+        let entry = raw_entry(&image, "kernel.elf");
+        let field = |offset: usize| u16::from_le_bytes([entry[offset], entry[offset + 1]]);
+        assert_eq!(entry[13], 0);
+        assert_eq!((field(14), field(16)), ((4 << 11) | (5 << 5) | 3, (21 << 9) | (2 << 5) | 3));
+        assert_eq!(field(18), (22 << 9) | (3 << 5) | 4);
+        assert_eq!((field(22), field(24)), ((6 << 11) | (7 << 5) | 4, (23 << 9) | (4 << 5) | 5));
+
+        // The name, attributes, cluster and size are kept
+        assert_eq!(entry[..13], original[..13]);
+        assert_eq!(entry[20..22], original[20..22]);
+        assert_eq!(entry[26..], original[26..]);
+    }
+
+    #[test]
+    fn long_names_are_removed() {
+        let directory = tempfile::tempdir().unwrap();
+        let image = image(directory.path(), &["long-file-name.txt"]);
+        let mut volume = FatVolume::open(&image, 0).unwrap();
+        let (_, entry) = volume.find("long-file-name.txt").unwrap();
+        assert!(!entry.long_name_indices.is_empty());
+
+        apply_layout(&image, 0, &[LayoutEntry {
+            path: String::from("long-file-name.txt"),
+            long_name: Some(false),
+            ..LayoutEntry::default()
+        }]).unwrap();
+        let (directory, renamed) = volume.find(&entry.short_name).unwrap();
+        assert_eq!((renamed.index, renamed.long_name), (entry.index, None));
+        assert!(entry.long_name_indices.iter().all(|index| directory.entry(*index)[0] == DELETED_ENTRY));
+        assert!(volume.find("long-file-name.txt").is_err());
+    }
+}
@@ -2,25 +2,41 @@
 
 use std::fmt::{Display, Formatter};
 use std::path;
+use std::path::Path;
 use std::process::exit;
-use clap::{Parser, Subcommand, ValueEnum};
+use clap::{ArgGroup, Args, Parser, Subcommand, ValueEnum};
 use colorful::{Color, Colorful};
 use log::{error, info, Level};
 use crate::arch::Architecture;
-use crate::error::{EXIT_BUILD_ERROR, EXIT_INVALID_WORKSPACE, EXIT_QEMU_ERROR};
-use crate::project::{CargoProject, load_from_workspace};
-use crate::tasks::build::build_image;
-use crate::tasks::qemu::run_qemu;
+use crate::config::Config;
+use crate::error::{EXIT_BUILD_ERROR, EXIT_INVALID_CONFIG, EXIT_INVALID_WORKSPACE, EXIT_KEY_GENERATION_ERROR, EXIT_QEMU_ERROR,
+                   EXIT_TEST_ERROR};
+use crate::project::{CargoProject, load_from_workspace, ProjectKind};
+use crate::tasks::build::{build_image, build_projects};
+use crate::tasks::debugger::Debugger;
+use crate::tasks::qemu::devices::{Disk, Network, parse_disk};
+use crate::tasks::qemu::firmware::Firmware;
+use crate::tasks::qemu::profile::Storage;
+use crate::tasks::qemu::qmp::{QmpCommand, run_qmp_command};
+use crate::tasks::runner::{RunnerKind, run_image, runner_backend};
+use crate::tasks::secureboot::generate_keys;
+use crate::tasks::test::run_tests;
+use crate::tasks::watch::{watch_and_run, watch_image};
+use crate::utils::parse_address;
 use crate::validate::find_manifest_and_validate;
 
 pub(crate) mod validate;
+pub(crate) mod config;
 pub(crate) mod error;
 pub(crate) mod project;
 pub(crate) mod arch;
 pub(crate) mod tasks;
 pub(crate) mod image;
+pub(crate) mod layout;
+pub(crate) mod symbolizer;
 pub(crate) mod utils;
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(ValueEnum, Clone, Copy, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
 enum ImageType {
     UEFI,
@@ -33,6 +49,17 @@ impl Display for ImageType {
     }
 }
 
+/// The format of the artifact the Operating System is booted from
+#[derive(ValueEnum, Clone, Copy, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub(crate) enum ImageFormat {
+    /// ISO file with the FAT image as El Torito boot image
+    Iso,
+    /// Raw FAT image file
+    Raw,
+    /// Host directory with the files of the EFI system partition (exposed as virtual FAT drive by QEMU)
+    Directory
+}
+
 #[derive(Parser, Clone)]
 #[command(author, version)]
 pub(crate) struct Arguments {
@@ -56,43 +83,343 @@ pub(crate) struct Arguments {
     command: SubCommand
 }
 
+/// The names of the artifacts can contain the placeholders `{name}`, `{version}`, `{arch}` and `{profile}`
+#[derive(Args, Clone)]
+pub(crate) struct ArtifactArguments {
+    /// The name of the image file that should be built by this tool
+    #[arg(long, default_value = "image.img")]
+    pub(crate) image_file: String,
+
+    /// The name of the ISO file that should be built by this tool
+    #[arg(long, default_value = "image.iso")]
+    pub(crate) iso_file: String,
+
+    /// The directory into which the files are copied, if the format is directory
+    #[arg(long, default_value = "esp")]
+    pub(crate) esp_directory: String,
+
+    /// The format of the built artifact
+    #[arg(long, default_value = "iso")]
+    pub(crate) format: ImageFormat,
+
+    /// The directory (relative to the workspace) in which the artifacts are placed, overrides the config
+    #[arg(long)]
+    pub(crate) out_dir: Option<String>
+}
+
+impl ArtifactArguments {
+    /// Replace the placeholders in the names of the artifacts and place the artifacts in the output directory
+    fn resolve(&mut self, out_dir: Option<&str>, placeholders: &[(&str, String)]) {
+        for name in [&mut self.image_file, &mut self.iso_file, &mut self.esp_directory] {
+            let resolved = placeholders.iter().fold(name.clone(), |name, (placeholder, value)| {
+                name.replace(placeholder, value)
+            });
+            *name = match out_dir {
+                Some(out_dir) => Path::new(out_dir).join(resolved).to_str().unwrap().to_owned(),
+                None => resolved
+            };
+        }
+    }
+}
+
+#[derive(Args, Clone)]
+pub(crate) struct BuildArguments {
+    #[arg(long, default_value_t = 512)]
+    pub(crate) block_size: u16,
+
+    #[arg(long, default_value_t = 93750)]
+    pub(crate) block_count: u32,
+
+    /// The private key used to sign the EFI binaries for Secure Boot
+    #[arg(long, requires = "sign_cert")]
+    pub(crate) sign_key: Option<String>,
+
+    /// The certificate used to sign the EFI binaries for Secure Boot
+    #[arg(long, requires = "sign_key")]
+    pub(crate) sign_cert: Option<String>,
+
+    /// Overwrite existing files and non-empty directories, which weren't generated by this tool
+    #[arg(long, default_value_t = false)]
+    pub(crate) force: bool,
+
+    /// Keep the intermediate FAT image of the ISO file and the staged files of custom partitions
+    #[arg(long, default_value_t = false)]
+    pub(crate) keep_intermediate: bool
+}
+
+#[derive(Args, Clone)]
+pub(crate) struct WatchArguments {
+    /// Watch the sources of all projects and rebuild the projects and the image on changes
+    #[arg(long, default_value_t = false)]
+    pub(crate) watch: bool,
+
+    /// The time in milliseconds without further changes, before the projects are rebuilt
+    #[arg(long, default_value_t = 500, requires = "watch")]
+    pub(crate) debounce: u64,
+
+    /// Restart QEMU with the updated image after each rebuild (only used by `run`)
+    #[arg(long, default_value_t = false, requires = "watch")]
+    pub(crate) restart: bool
+}
+
+#[derive(Args, Clone)]
+#[command(group(ArgGroup::new("stop_condition").multiple(true).args(["expect", "fail_on", "timeout"])))]
+pub(crate) struct QemuArguments {
+    /// The emulator or hypervisor the artifact is run with, overrides the config (default: qemu)
+    #[arg(long)]
+    pub(crate) runner: Option<RunnerKind>,
+
+    /// The command template of the custom runner (e.g. `my-emulator --cdrom {iso} --memory {memory}`), overrides the
+    /// config
+    #[arg(long)]
+    pub(crate) runner_command: Option<String>,
+
+    /// Boot the kernel binary directly with `-kernel` (Multiboot, Linux boot protocol or PVH) without firmware and
+    /// image. The `run` command only builds the kernel project.
+    #[arg(long, default_value_t = false, conflicts_with = "secure_boot_vars")]
+    pub(crate) direct: bool,
+
+    /// The initial ramdisk passed with `-initrd`, if the kernel is booted directly
+    #[arg(long, requires = "direct")]
+    pub(crate) initrd: Option<String>,
+
+    /// The command line passed with `-append`, if the kernel is booted directly
+    #[arg(long, requires = "direct")]
+    pub(crate) append: Option<String>,
+
+    /// The storage interface the raw image or directory is attached to (default: depends on the architecture)
+    #[arg(long)]
+    pub(crate) storage: Option<Storage>,
+
+    /// The network backend of the virtual network card, overrides the config (default: QEMU default network)
+    #[arg(long)]
+    pub(crate) net: Option<Network>,
+
+    /// The model of the virtual network card (e.g. e1000, virtio-net-pci), overrides the config (default: depends on
+    /// the architecture)
+    #[arg(long)]
+    pub(crate) nic_model: Option<String>,
+
+    /// The TAP interface of the host used by the `tap` network backend, overrides the config
+    #[arg(long)]
+    pub(crate) tap_interface: Option<String>,
+
+    /// Attach an additional disk image as `path[:interface]` (interface: virtio, ahci, nvme or usb), appended to the
+    /// disks of the config
+    #[arg(long, value_parser = parse_disk)]
+    pub(crate) disk: Vec<Disk>,
+
+    /// Attach a USB keyboard and tablet on a XHCI controller
+    #[arg(long, default_value_t = false)]
+    pub(crate) usb: bool,
+
+    /// Attach a device with the raw QEMU device specification (e.g. `virtio-rng-pci`), appended to the devices of the
+    /// config
+    #[arg(long)]
+    pub(crate) device: Vec<String>,
+
+    /// Should QEMU be started with debugger enabled (QEMU will wait for the connection before
+    /// running the image code)
+    #[arg(long, short, default_value_t = false)]
+    pub(crate) debugging: bool,
+
+    /// If debugging is enabled, the port of the GDB server for qemu
+    #[arg(long, default_value_t = 1337)]
+    pub(crate) debug_port: u16,
+
+    /// If debugging is enabled, the debugger for which the init script is generated
+    #[arg(long, default_value = "gdb")]
+    pub(crate) debugger: Debugger,
+
+    /// If debugging is enabled, launch the debugger with the generated init script attached to QEMU
+    #[arg(long, default_value_t = false, requires = "debugging")]
+    pub(crate) attach_debugger: bool,
+
+    /// The address the firmware loads the bootloader to. The bootloader symbols are relocated from the PE image base
+    /// to this address
+    #[arg(long, value_parser = parse_address)]
+    pub(crate) bootloader_load_address: Option<u64>,
+
+    /// If this is enabled, QEMU will print register and exception information to stdout
+    #[arg(long, short, default_value_t = false)]
+    pub(crate) exception_info: bool,
+
+    /// The memory size of the virtual machine (e.g. 512, 2G), overrides the config (default: 512)
+    #[arg(long)]
+    pub(crate) memory: Option<String>,
+
+    /// The count of CPUs of the virtual machine, overrides the config
+    #[arg(long)]
+    pub(crate) smp: Option<u32>,
+
+    /// The machine type of the virtual machine (e.g. q35), overrides the config
+    #[arg(long)]
+    pub(crate) machine: Option<String>,
+
+    /// The CPU model of the virtual machine (e.g. host, qemu64), overrides the config
+    #[arg(long)]
+    pub(crate) cpu: Option<String>,
+
+    /// Enable KVM acceleration (falls back to TCG if KVM is not available), overrides the config
+    #[arg(long, default_value_t = false, conflicts_with = "no_kvm")]
+    pub(crate) kvm: bool,
+
+    /// Disable KVM acceleration, overrides the config
+    #[arg(long, default_value_t = false)]
+    pub(crate) no_kvm: bool,
+
+    /// The firmware used to boot the image, overrides the config and the default firmware of the architecture
+    #[arg(long)]
+    pub(crate) firmware: Option<Firmware>,
+
+    /// Directory which is searched first for the firmware files, overrides the config
+    #[arg(long)]
+    pub(crate) firmware_path: Option<String>,
+
+    /// Replace the persistent variable store of the workspace with a fresh copy of the template
+    #[arg(long, default_value_t = false)]
+    pub(crate) reset_nvram: bool,
+
+    /// Disable the graphical display of QEMU and redirect the serial output to stdio
+    #[arg(long, default_value_t = false)]
+    pub(crate) headless: bool,
+
+    /// Write the serial output into the specified log file
+    #[arg(long)]
+    pub(crate) serial_log: Option<String>,
+
+    /// Stop QEMU successfully when a line of the serial output matches this regular expression (fails if QEMU exits
+    /// before)
+    #[arg(long)]
+    pub(crate) expect: Option<String>,
+
+    /// Stop QEMU with failure when a line of the serial output matches this regular expression
+    #[arg(long)]
+    pub(crate) fail_on: Option<String>,
+
+    /// Stop QEMU with failure when it runs longer than the specified seconds
+    #[arg(long)]
+    pub(crate) timeout: Option<u64>,
+
+    /// Write the content of the display into the specified file before QEMU is stopped by a pattern or the timeout
+    /// (requires --expect, --fail-on or --timeout)
+    #[arg(long, requires = "stop_condition")]
+    pub(crate) screendump_on_exit: Option<String>,
+
+    /// Save a snapshot with the specified name before QEMU is stopped by a pattern or the timeout (requires a
+    /// writable qcow2 drive and --expect, --fail-on or --timeout)
+    #[arg(long, requires = "stop_condition")]
+    pub(crate) savevm: Option<String>,
+
+    /// Restore the snapshot with the specified name when QEMU is started
+    #[arg(long)]
+    pub(crate) loadvm: Option<String>,
+
+    /// Rewrite the addresses in the serial output into functions and source lines of the kernel (uses the DWARF
+    /// debug information of the kernel binary)
+    #[arg(long, default_value_t = false)]
+    pub(crate) symbolize: bool,
+
+    /// The OVMF variable store with enrolled Secure Boot keys. If specified, QEMU boots the image with the
+    /// Secure Boot firmware (e.g. OVMF_CODE.secboot.fd)
+    #[arg(long)]
+    pub(crate) secure_boot_vars: Option<String>
+}
+
+#[derive(Args, Clone)]
+pub(crate) struct TestArguments {
+    /// The exit code of QEMU reporting passed tests with `isa-debug-exit` on x86, overrides the config
+    /// (default: 33)
+    #[arg(long)]
+    pub(crate) success_exit_code: Option<i32>,
+
+    /// Write the results as JUnit XML file (relative to the workspace), overrides the config
+    #[arg(long)]
+    pub(crate) junit: Option<String>,
+
+    /// Write the results as TAP file (relative to the workspace), overrides the config
+    #[arg(long)]
+    pub(crate) tap: Option<String>
+}
+
 #[derive(Subcommand, Clone)]
 enum SubCommand {
     /// Build the image file with this Rust project or workspace
     BuildImage {
-        /// The name of the image file that should be built by this tool
-        #[arg(long, default_value = "image.img")]
-        image_file: String,
-
-        /// The name of the ISO file that should be built by this tool
-        #[arg(long, default_value = "image.iso")]
-        iso_file: String,
+        #[command(flatten)]
+        artifact: ArtifactArguments,
 
-        #[arg(long, default_value_t = 512)]
-        block_size: u16,
+        #[command(flatten)]
+        build: BuildArguments,
 
-        #[arg(long, default_value_t = 93750)]
-        block_count: u32
+        #[command(flatten)]
+        watch: WatchArguments
     },
 
     /// Run the built image in QEMU
     RunQEMU {
-        /// The name of the ISO file that should be built by this tool
-        #[arg(long, default_value = "image.iso")]
-        iso_file: String,
-
-        /// Should QEMU be started with debugger enabled (QEMU will wait for the connection before
-        /// running the image code)
-        #[arg(long, short, default_value_t = false)]
-        debugging: bool,
-
-        /// If debugging is enabled, the port of the GDB server for qemu
-        #[arg(long, default_value_t = 1337)]
-        debug_port: u16,
-
-        /// If this is enabled, QEMU will print register and exception information to stdout
-        #[arg(long, short, default_value_t = false)]
-        exception_info: bool
+        #[command(flatten)]
+        artifact: ArtifactArguments,
+
+        #[command(flatten)]
+        qemu: QemuArguments
+    },
+
+    /// Build the image file and run it in QEMU. If a kernel binary is specified, it is used instead of building the
+    /// kernel project, so this command can be used as runner of Cargo (`runner = "osimage run"`).
+    Run {
+        #[command(flatten)]
+        artifact: ArtifactArguments,
+
+        #[command(flatten)]
+        build: BuildArguments,
+
+        #[command(flatten)]
+        qemu: QemuArguments,
+
+        #[command(flatten)]
+        watch: WatchArguments,
+
+        /// The kernel binary that should be used instead of building the kernel project
+        #[arg(conflicts_with = "watch")]
+        kernel: Option<String>,
+
+        /// Arguments passed by Cargo to the runner (ignored)
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true, hide = true)]
+        runner_args: Vec<String>
+    },
+
+    /// Build the test harness of the kernel projects and run each test binary headless in QEMU
+    Test {
+        #[command(flatten)]
+        artifact: ArtifactArguments,
+
+        #[command(flatten)]
+        build: BuildArguments,
+
+        #[command(flatten)]
+        qemu: QemuArguments,
+
+        #[command(flatten)]
+        test: TestArguments
+    },
+
+    /// Control the QEMU instance started by `run-qemu` or `run` over the QEMU Machine Protocol
+    Qemu {
+        #[command(subcommand)]
+        command: QmpCommand
+    },
+
+    /// Generate a test PK/KEK/db set and enroll them into an OVMF variable store
+    GenerateKeys {
+        /// The directory in which the keys, certificates and the enrolled variable store are stored
+        #[arg(long, default_value = "target/osimage/secureboot")]
+        output_directory: String,
+
+        /// The OVMF variable store template into which the keys are enrolled (default: located like the firmware of
+        /// run-qemu)
+        #[arg(long)]
+        ovmf_vars: Option<String>
     }
 }
 
@@ -108,7 +435,7 @@ fn main() {
     info!("{}", "                                       /____/           ".gradient(Color::Red));
     info!("        {} Creation Tool by {}", "OS Image".gradient(Color::Red), "Cach30verfl0w"
         .gradient(Color::Green));
-    let args = Arguments::parse();
+    let mut args = Arguments::parse();
     log::set_max_level(args.level.unwrap_or(Level::Info).to_level_filter());
     info!("Targeting {} architecture ({})", args.target_arch, if args.target_arch.is64bit() { "64-bit" }
         else { "32-bit" });
@@ -122,6 +449,32 @@ fn main() {
         }
     };
 
+    let config = match Config::from_manifest(&manifest) {
+        Ok(config) => config,
+        Err(error) => {
+            error!("Unable to read OSImage configuration of specified workspace => {}", error);
+            exit(EXIT_INVALID_CONFIG);
+        }
+    };
+
+    // Resolve the names of the artifacts with the package of the root manifest. The projects are always built with
+    // the dev profile.
+    let workspace_package = manifest.workspace.as_ref().and_then(|workspace| workspace.package.as_ref());
+    let name = manifest.package.as_ref().map(|package| package.name().to_owned())
+        .or(path::absolute(&args.workspace_path).ok()
+            .and_then(|path| path.file_name().map(|name| name.to_string_lossy().into_owned())))
+        .unwrap_or(String::from("image"));
+    let version = manifest.package.as_ref().and_then(|package| package.version.get().ok().cloned())
+        .or(workspace_package.and_then(|package| package.version.clone()))
+        .unwrap_or(String::from("0.0.0"));
+    let placeholders = [("{name}", name), ("{version}", version), ("{arch}", String::from(args.target_arch)),
+        ("{profile}", String::from("debug"))];
+    if let SubCommand::BuildImage { artifact, .. } | SubCommand::RunQEMU { artifact, .. }
+        | SubCommand::Run { artifact, .. } | SubCommand::Test { artifact, .. } = &mut args.command {
+        let out_dir = artifact.out_dir.clone().or(config.artifact.out_dir.clone());
+        artifact.resolve(out_dir.as_deref(), &placeholders);
+    }
+
     let is_workspace = manifest.workspace.is_some();
     info!("Located {} manifest file in directory {}",
         if is_workspace { "Workspace" } else { "project" },
@@ -140,13 +493,24 @@ fn main() {
             }
         });
     } else {
-        projects.push(CargoProject::from_manifest(args.workspace_path.clone(), manifest));
+        projects.push(match CargoProject::from_manifest(args.workspace_path.clone(), manifest) {
+            Ok(project) => project,
+            Err(error) => {
+                error!("Unable to parse manifest of project => {}", error);
+                exit(EXIT_INVALID_WORKSPACE);
+            }
+        });
     }
 
     // Switch to selected command
     match &args.command {
-        SubCommand::BuildImage { iso_file, image_file, block_size, block_count  } => {
-            match build_image(&args, projects, image_file, iso_file, block_size, block_count) {
+        SubCommand::BuildImage { artifact, build, watch } => {
+            let result = match watch.watch {
+                true => watch_image(&args, &config, projects, artifact, build, watch),
+                false => build_image(&args, &config, projects, artifact, build, None)
+            };
+
+            match result {
                 Ok(()) => {}
                 Err(error) => {
                     error!("Unable to build Operating System image => {}", error);
@@ -154,14 +518,128 @@ fn main() {
                 }
             }
         },
-        SubCommand::RunQEMU { iso_file, debugging, debug_port, exception_info } => {
-            match run_qemu(&args, iso_file, *debugging, *debug_port, *exception_info) {
+        SubCommand::RunQEMU { artifact, qemu } => {
+            match run_image(&args, &config, &projects, artifact, qemu, None) {
                 Ok(()) => {}
                 Err(error) => {
-                    error!("Unable to run image in QEMU => {}", error);
+                    error!("Unable to run image => {}", error);
                     exit(EXIT_QEMU_ERROR);
                 }
             }
+        },
+        SubCommand::Run { artifact, build, qemu, watch, .. } if watch.watch => {
+            match watch_and_run(&args, &config, projects, artifact, build, qemu, watch) {
+                Ok(()) => {}
+                Err(error) => {
+                    error!("Unable to build and run image in watch mode => {}", error);
+                    exit(EXIT_BUILD_ERROR);
+                }
+            }
+        },
+        SubCommand::Run { artifact, build, qemu, kernel, .. } => {
+            // Reject the options unsupported by the runner before building
+            if let Err(error) = runner_backend(&config, qemu) {
+                error!("Unable to run image => {}", error);
+                exit(EXIT_QEMU_ERROR);
+            }
+
+            // The image isn't needed, if the kernel is booted directly
+            let build_result = match (qemu.direct, kernel) {
+                (true, Some(_)) => Ok(()),
+                (true, None) => build_projects(&args, &projects, ProjectKind::Kernel),
+                (false, kernel) => build_image(&args, &config, projects.clone(), artifact, build,
+                    kernel.as_ref().map(Path::new))
+            };
+
+            if let Err(error) = build_result {
+                error!("Unable to build Operating System image => {}", error);
+                exit(EXIT_BUILD_ERROR);
+            }
+
+            match run_image(&args, &config, &projects, artifact, qemu, kernel.as_ref().map(Path::new)) {
+                Ok(()) => {}
+                Err(error) => {
+                    error!("Unable to run image => {}", error);
+                    exit(EXIT_QEMU_ERROR);
+                }
+            }
+        },
+        SubCommand::Test { artifact, build, qemu, test } => {
+            match run_tests(&args, &config, &projects, artifact, build, qemu, test) {
+                Ok(()) => {}
+                Err(error) => {
+                    error!("Unable to run kernel tests => {}", error);
+                    exit(EXIT_TEST_ERROR);
+                }
+            }
+        },
+        SubCommand::Qemu { command } => {
+            match run_qmp_command(&args, command) {
+                Ok(()) => {}
+                Err(error) => {
+                    error!("Unable to control QEMU => {}", error);
+                    exit(EXIT_QEMU_ERROR);
+                }
+            }
+        },
+        SubCommand::GenerateKeys { output_directory, ovmf_vars } => {
+            match generate_keys(&args, &config, output_directory, ovmf_vars) {
+                Ok(()) => {}
+                Err(error) => {
+                    error!("Unable to generate Secure Boot keys => {}", error);
+                    exit(EXIT_KEY_GENERATION_ERROR);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ArtifactArguments, ImageFormat};
+
+    fn artifact_arguments(image_file: &str, iso_file: &str, esp_directory: &str) -> ArtifactArguments {
+        ArtifactArguments {
+            image_file: image_file.to_owned(),
+            iso_file: iso_file.to_owned(),
+            esp_directory: esp_directory.to_owned(),
+            format: ImageFormat::Iso,
+            out_dir: None
         }
     }
+
+    fn placeholders() -> [(&'static str, String); 4] {
+        [("{name}", String::from("kernel")), ("{version}", String::from("0.1.0")), ("{arch}", String::from("x86_64")),
+            ("{profile}", String::from("debug"))]
+    }
+
+    #[test]
+    fn resolve_placeholders() {
+        let mut artifact = artifact_arguments("{name}.img", "{name}-{version}-{arch}-{profile}.iso",
+            "esp-{arch}-{arch}");
+        artifact.resolve(None, &placeholders());
+        assert_eq!(artifact.image_file, "kernel.img");
+        assert_eq!(artifact.iso_file, "kernel-0.1.0-x86_64-debug.iso");
+        assert_eq!(artifact.esp_directory, "esp-x86_64-x86_64");
+
+        let mut artifact = artifact_arguments("image.img", "{unknown}.iso", "{name");
+        artifact.resolve(None, &placeholders());
+        assert_eq!((artifact.image_file.as_str(), artifact.iso_file.as_str()), ("image.img", "{unknown}.iso"));
+        assert_eq!(artifact.esp_directory, "{name");
+    }
+
+    #[test]
+    fn resolve_out_dir() {
+        let mut artifact = artifact_arguments("{name}.img", "images/image.iso", "esp");
+        artifact.resolve(Some("target/images"), &placeholders());
+        assert_eq!(artifact.image_file, "target/images/kernel.img");
+        assert_eq!(artifact.iso_file, "target/images/images/image.iso");
+        assert_eq!(artifact.esp_directory, "target/images/esp");
+
+        // Absolute names aren't placed in the output directory
+        let mut artifact = artifact_arguments("/tmp/{name}.img", "image.iso", "esp");
+        artifact.resolve(Some("/srv/images"), &placeholders());
+        assert_eq!(artifact.image_file, "/tmp/kernel.img");
+        assert_eq!(artifact.iso_file, "/srv/images/image.iso");
+    }
 }
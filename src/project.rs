@@ -7,7 +7,7 @@ use glob::glob;
 use log::{debug, info};
 use toml::Value;
 use crate::error::Error;
-use crate::validate::find_manifest_and_validate;
+use crate::validate::{BootProtocol, find_manifest_and_validate};
 use colorful::Color;
 use crate::arch::Architecture;
 
@@ -34,7 +34,7 @@ impl Display for ProjectKind {
 
 impl ProjectKind {
     pub fn target(&self, project: &CargoProject, architecture: Architecture) -> Option<String> {
-        project.target.clone().map(|value| Some(value)).unwrap_or(match self {
+        project.target.clone().map(Some).unwrap_or(match self {
             ProjectKind::Kernel => Some(format!("{}-unknown-none.json", String::from(architecture))),
             ProjectKind::Bootloader => Some(format!("{}-unknown-uefi", String::from(architecture))),
             ProjectKind::SharedLibrary => None,
@@ -65,9 +65,9 @@ impl ProjectKind {
         }
     }
 
-    pub fn image_target_file(&self, project: &CargoProject, architecture: Architecture, _project: &str) -> Option<String> {
+    pub fn image_target_file(&self, project: &CargoProject, architecture: Architecture) -> Option<String> {
         // TODO: All non-kernel and non-bootloader projects are ignored by the build system
-        project.image_path.clone().map(|string| Some(string)).unwrap_or(match self {
+        project.image_path.clone().map(Some).unwrap_or(match self {
             ProjectKind::Kernel => Some(String::from("EFI/BOOT/KERNEL.ELF")),
             ProjectKind::Bootloader => Some(architecture.efi_boot_file()),
             ProjectKind::SharedLibrary => None,
@@ -84,17 +84,19 @@ pub struct CargoProject {
     pub path: String,
     pub kind: ProjectKind,
     pub target: Option<String>,
-    pub image_path: Option<String>
+    pub image_path: Option<String>,
+    pub boot_protocol: Option<BootProtocol>
 }
 
 impl CargoProject {
 
     #[inline]
-    pub fn from_manifest(path: String, manifest: Manifest<Value>) -> Self {
+    pub fn from_manifest(path: String, manifest: Manifest<Value>) -> Result<Self, Error> {
         // Get kind of project
 
         let mut target = None;
         let mut image_path = None;
+        let mut boot_protocol = None;
         let kind = match &manifest.package().metadata {
             None => {
                 if Path::new(&path).join("src/main.rs").exists() {
@@ -103,9 +105,8 @@ impl CargoProject {
                     match &manifest.lib {
                         None => ProjectKind::StaticLibrary,
                         Some(lib) => {
-                            if lib.crate_type.contains(&String::from("cdylib")) {
-                                ProjectKind::SharedLibrary
-                            } else if lib.crate_type.contains(&String::from("dylib")) {
+                            if lib.crate_type.contains(&String::from("cdylib"))
+                                || lib.crate_type.contains(&String::from("dylib")) {
                                 ProjectKind::SharedLibrary
                             } else {
                                 ProjectKind::StaticLibrary
@@ -129,6 +130,14 @@ impl CargoProject {
                     Some(value) => image_path = Some(value.as_str().unwrap().to_owned())
                 }
 
+                // Load boot protocol
+                match osimage_data.get("boot_protocol") {
+                    None => {}
+                    Some(value) => boot_protocol = Some(value.as_str().and_then(BootProtocol::from_name)
+                        .ok_or(Error::InvalidConfig(format!("Invalid boot protocol {} of project '{}', expected \
+                        multiboot, multiboot2 or limine", value, manifest.package().name())))?)
+                }
+
                 // Load kind
                 match osimage_data["kind"].as_str().unwrap() {
                     "kernel" => ProjectKind::Kernel,
@@ -139,13 +148,14 @@ impl CargoProject {
         };
 
         // Return structure
-        Self {
+        Ok(Self {
             path,
             kind,
             target,
             image_path,
+            boot_protocol,
             manifest
-        }
+        })
     }
 
 }
@@ -171,7 +181,7 @@ pub fn load_from_workspace<P: AsRef<Path>>(base_path: &P, members: Vec<String>)
             let manifest = find_manifest_and_validate(path.clone())?;
             debug!(" - Valid Manifest: {} ({}) => {}", manifest.package().name().gradient(Color::Green),
                 manifest.package().version(), absolute(&path)?.to_str().unwrap().gradient(Color::Red));
-            projects.push(CargoProject::from_manifest(path.to_string_lossy().into_owned(), manifest));
+            projects.push(CargoProject::from_manifest(path.to_string_lossy().into_owned(), manifest)?);
         }
     }
     let duration = SystemTime::now().duration_since(start_time).unwrap();
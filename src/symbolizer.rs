@@ -0,0 +1,212 @@
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::Path;
+use addr2line::Context;
+use addr2line::gimli::{EndianRcSlice, RunTimeEndian};
+use addr2line::object::{File, Object, ObjectSection, ObjectSymbol, SectionKind, SymbolKind};
+use regex::{Captures, Regex};
+use crate::error::Error;
+
+/// A frame of a symbolized address. If the address is in an inlined function, the address resolves to multiple frames.
+#[derive(Clone, Debug)]
+pub(crate) struct Frame {
+    pub(crate) function: Option<String>,
+    pub(crate) file: Option<String>,
+    pub(crate) line: Option<u32>,
+    pub(crate) inlined: bool
+}
+
+impl Display for Frame {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(formatter, "{}", self.function.as_deref().unwrap_or("??"))?;
+        if let Some(file) = self.file.as_ref() {
+            write!(formatter, " at {}", file)?;
+            if let Some(line) = self.line {
+                write!(formatter, ":{}", line)?;
+            }
+        }
+
+        if self.inlined {
+            write!(formatter, " (inlined)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Symbolizer of addresses in the kernel ELF file. The DWARF debug information is used to resolve functions, inlined
+/// frames and source lines. Without debug information, the address is resolved to the function of the symbol table.
+pub(crate) struct Symbolizer {
+    context: Context<EndianRcSlice<RunTimeEndian>>,
+    symbols: Vec<(u64, u64, String)>,
+    text_sections: Vec<(u64, u64)>,
+    address_pattern: Regex
+}
+
+impl Symbolizer {
+    /// Load the debug information and symbol table of the specified ELF file
+    pub(crate) fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let invalid = |error: String| Error::SymbolizerError(path.as_ref().to_string_lossy().into_owned(), error);
+        let data = fs::read(&path)?;
+        let file = File::parse(&*data).map_err(|error| invalid(error.to_string()))?;
+        let context = Context::new(&file).map_err(|error| invalid(error.to_string()))?;
+
+        let mut symbols: Vec<(u64, u64, String)> = file.symbols()
+            .filter(|symbol| symbol.kind() == SymbolKind::Text && symbol.address() != 0)
+            .filter_map(|symbol| symbol.name().ok().map(|name| (symbol.address(), symbol.size(), name.to_owned())))
+            .collect();
+        symbols.sort_by_key(|(address, _, _)| *address);
+        let text_sections = file.sections().filter(|section| section.kind() == SectionKind::Text)
+            .map(|section| (section.address(), section.address() + section.size()))
+            .collect();
+
+        Ok(Symbolizer {
+            context,
+            symbols,
+            text_sections,
+            address_pattern: Regex::new(r"\b0x[0-9a-fA-F]{4,16}\b").unwrap()
+        })
+    }
+
+    /// Resolve the frames of the specified address. The innermost inlined function is the first frame. Addresses
+    /// outside of the code sections aren't resolved.
+    pub(crate) fn frames(&self, address: u64) -> Vec<Frame> {
+        let mut frames = Vec::new();
+        if !self.text_sections.iter().any(|(start, end)| (*start..*end).contains(&address)) {
+            return frames;
+        }
+
+        if let Ok(mut iterator) = self.context.find_frames(address).skip_all_loads() {
+            while let Ok(Some(frame)) = iterator.next() {
+                frames.push(Frame {
+                    function: frame.function.as_ref()
+                        .and_then(|function| function.demangle().ok().map(|name| name.into_owned())),
+                    file: frame.location.as_ref().and_then(|location| location.file.map(String::from)),
+                    line: frame.location.as_ref().and_then(|location| location.line),
+                    inlined: false
+                });
+            }
+        }
+
+        // The frames before the outermost frame are inlined into their caller
+        let count = frames.len();
+        frames.iter_mut().take(count.saturating_sub(1)).for_each(|frame| frame.inlined = true);
+
+        // Fall back to the symbol table, if no function was found in the debug information
+        if frames.iter().all(|frame| frame.function.is_none()) {
+            if let Some(name) = self.symbol(address) {
+                match frames.first_mut() {
+                    Some(frame) => frame.function = Some(name),
+                    None => frames.push(Frame { function: Some(name), file: None, line: None, inlined: false })
+                }
+            }
+        }
+        frames
+    }
+
+    /// Resolve the demangled name of the function containing the address by the symbol table
+    fn symbol(&self, address: u64) -> Option<String> {
+        let index = self.symbols.partition_point(|(start, _, _)| *start <= address).checked_sub(1)?;
+        let (start, size, name) = &self.symbols[index];
+        if *size != 0 && address >= start + size {
+            return None;
+        }
+        Some(addr2line::demangle_auto(name.into(), None).into_owned())
+    }
+
+    /// Describe the address with all frames (e.g. `kernel::main at src/main.rs:12`) or return `None`, if the address
+    /// can't be resolved
+    pub(crate) fn describe(&self, address: u64) -> Option<String> {
+        let frames = self.frames(address);
+        if frames.is_empty() {
+            return None;
+        }
+        Some(frames.iter().map(ToString::to_string).collect::<Vec<_>>().join(" <- "))
+    }
+
+    /// Rewrite the hexadecimal addresses in the line, which are resolved by the symbolizer, into the frames of the
+    /// address (e.g. `0xffff800000001234` into `kernel::main at src/main.rs:12 (0xffff800000001234)`). The addresses
+    /// are return addresses of a backtrace, which point behind the call instruction, so the address before is resolved
+    /// to get the line and the inlined functions of the call.
+    pub(crate) fn symbolize_line(&self, line: &str) -> String {
+        self.address_pattern.replace_all(line, |captures: &Captures| {
+            let address = &captures[0];
+            let call_address = u64::from_str_radix(&address[2..], 16).ok().and_then(|value| value.checked_sub(1));
+            match call_address.and_then(|value| self.describe(value)) {
+                Some(description) => format!("{} ({})", description, address),
+                None => address.to_owned()
+            }
+        }).into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::hint::black_box;
+    use crate::symbolizer::Symbolizer;
+
+    #[inline(always)]
+    fn inlined_fixture(value: u64) -> u64 {
+        black_box(value) ^ 0x5A5A
+    }
+
+    #[inline(never)]
+    fn outer_fixture(value: u64) -> u64 {
+        inlined_fixture(value) + 1
+    }
+
+    /// Load the symbolizer of the test binary and return the address and size of the function in the symbol table
+    fn load(function: &str) -> (Symbolizer, u64, u64) {
+        black_box(outer_fixture(1));
+        let symbolizer = Symbolizer::load(env::current_exe().unwrap()).unwrap();
+        let (address, size) = symbolizer.symbols.iter()
+            .find(|(_, _, name)| addr2line::demangle_auto(name.into(), None).ends_with(function))
+            .map(|(address, size, _)| (*address, *size))
+            .unwrap();
+        (symbolizer, address, size)
+    }
+
+    #[test]
+    fn resolves_functions_and_lines() {
+        let (symbolizer, address, _) = load("symbolizer::tests::outer_fixture");
+        let frames = symbolizer.frames(address);
+        assert_eq!(frames.len(), 1);
+        assert!(frames[0].function.as_ref().unwrap().ends_with("symbolizer::tests::outer_fixture"));
+        assert!(frames[0].file.as_ref().unwrap().ends_with("symbolizer.rs"));
+        assert!(frames[0].line.is_some());
+        assert!(!frames[0].inlined);
+        assert!(symbolizer.frames(0x10).is_empty());
+    }
+
+    #[test]
+    fn resolves_inlined_frames() {
+        let (symbolizer, address, size) = load("symbolizer::tests::outer_fixture");
+        let frames = (address..address + size).map(|address| symbolizer.frames(address))
+            .find(|frames| frames.len() == 2)
+            .unwrap();
+        assert!(frames[0].function.as_ref().unwrap().ends_with("symbolizer::tests::inlined_fixture"));
+        assert!(frames[0].inlined);
+        assert!(frames[1].function.as_ref().unwrap().ends_with("symbolizer::tests::outer_fixture"));
+        assert!(!frames[1].inlined);
+        assert!(frames[1].to_string().starts_with(frames[1].function.as_ref().unwrap()));
+        assert!(frames[0].to_string().ends_with(" (inlined)"));
+    }
+
+    #[test]
+    fn symbolizes_return_addresses() {
+        let (symbolizer, address, size) = load("symbolizer::tests::outer_fixture");
+        let description = symbolizer.describe(address).unwrap();
+        assert_eq!(symbolizer.symbolize_line(&format!("  at {:#x}", address + 1)),
+            format!("  at {} ({:#x})", description, address + 1));
+
+        // The return address behind the last call of a function belongs to the function before the address
+        let description = symbolizer.symbolize_line(&format!("{:#x}", address + size));
+        assert!(description.contains("outer_fixture"), "{}", description);
+        let description = symbolizer.symbolize_line(&format!("{:#x}", address));
+        assert!(!description.contains("outer_fixture"), "{}", description);
+
+        // Other numbers and addresses outside of the code are kept
+        assert_eq!(symbolizer.symbolize_line("panic at 0x0010 with code 0x12 and 0xzz"),
+            "panic at 0x0010 with code 0x12 and 0xzz");
+    }
+}
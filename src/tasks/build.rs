@@ -1,85 +1,322 @@
-use std::fs::{create_dir, remove_dir, remove_file};
-use std::path::Path;
+use std::fs::{copy, create_dir_all, read_dir, remove_dir, remove_dir_all, remove_file};
+use std::path;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use colorful::{Color, Colorful};
-use log::{debug, info};
-use crate::Arguments;
+use log::{debug, info, warn};
+use crate::{Arguments, ArtifactArguments, BuildArguments, ImageFormat};
+use crate::config::Config;
 use crate::error::Error;
-use crate::image::Image;
-use crate::project::CargoProject;
+use crate::image::{FatWriter, FileSystemWriter, check_overwrite, check_overwrite_directory, partition_files,
+                   partition_table, write_partition};
+use crate::layout::apply_layout;
+use crate::project::{CargoProject, ProjectKind};
+use crate::tasks::cache::{CacheManifest, hash_file};
+use crate::tasks::secureboot::sign_efi_binary;
 use crate::utils::find_in_path;
+use crate::validate::validate_boot_protocol;
 
-pub(crate) fn build_image(args: &Arguments, projects: Vec<CargoProject>, image_file: &String,
-                          iso_file: &String, block_size: &u16, block_count: &u32) -> Result<(), Error> {
-    info!("Build all in-memory loaded Rust projects");
+/// The destination the build artifacts are copied into
+enum Destination {
+    Image(Box<dyn FileSystemWriter>),
+    Directory(PathBuf)
+}
+
+impl Destination {
+    fn copy_into<HP: AsRef<Path>, IP: AsRef<Path>>(&mut self, host_file: HP, image_file: IP) -> Result<(), Error> {
+        match self {
+            Destination::Image(writer) => writer.copy_into(host_file.as_ref(), image_file.as_ref()),
+            Destination::Directory(directory) => {
+                let target_file = directory.join(image_file);
+                debug!("Copy {} to {}", host_file.as_ref().to_str().unwrap().gradient(Color::Cyan),
+                    target_file.to_str().unwrap().gradient(Color::Red));
+                create_dir_all(target_file.parent().unwrap())?;
+                copy(host_file, target_file)?;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Create the Cargo command with the specified subcommand for the project. If the project has a target, the command
+/// builds the standard library for the target.
+pub(crate) fn cargo_command(args: &Arguments, cargo_path: &Path, project: &CargoProject,
+                            subcommand: &str) -> Command {
+    let mut command = Command::new(cargo_path);
+    command.arg(subcommand)
+        .arg("--package")
+        .arg(project.manifest.package().name());
+    command.current_dir(&args.workspace_path);
+
+    if let Some(target) = project.kind.target(project, args.target_arch) {
+        command
+            .arg("--target").arg(&target)
+            .arg("-Zbuild-std=core,alloc,compiler_builtins")
+            .arg("-Zbuild-std-features=compiler-builtins-mem");
+    }
+    command
+}
+
+/// Build the project with `cargo build`
+fn build_project(args: &Arguments, cargo_path: &Path, project: &CargoProject) -> Result<(), Error> {
+    let project_name = project.manifest.package().name();
+    let mut command = cargo_command(args, cargo_path, project, "build");
+    if let Some(target) = project.kind.target(project, args.target_arch) {
+        info!("Run build task on {} ({}) with `{}` ({})", project_name.color(Color::Green),
+        project.kind.to_string().color(Color::Orange3), "cargo build".color(Color::Red), target.color(Color::Green));
+    } else {
+        info!("Run build task on {} ({}) with `{}`", project_name.color(Color::Green),
+        project.kind.to_string().color(Color::Orange3), "cargo build".color(Color::Red));
+    }
+
+    // Validate exit code
+    let exit_status = command.status()?;
+    if !exit_status.success() {
+        return Err(Error::BuildFailed(String::from(project_name), exit_status.code().unwrap()));
+    }
+    Ok(())
+}
+
+/// Build all projects of the specified kind without generating an artifact
+pub(crate) fn build_projects(args: &Arguments, projects: &[CargoProject], kind: ProjectKind) -> Result<(), Error> {
     let cargo_path = find_in_path("cargo").ok_or(Error::ExecutableNotFound(String::from("cargo")))?;
+    for project in projects.iter().filter(|project| project.kind == kind) {
+        build_project(args, &cargo_path, project)?;
+        if let Some(protocol) = project.boot_protocol {
+            let project_name = project.manifest.package().name();
+            if let Some(output_path) = project.kind.output_file_path(project, args.target_arch, project_name) {
+                validate_boot_protocol(Path::new(&args.workspace_path).join(output_path), protocol)?;
+            }
+        }
+    }
+    Ok(())
+}
 
-    // Generate image
-    let image_path = Path::new(&args.workspace_path).join(".image");
-    if !image_path.exists() {
-        debug!("Directory {} not found! Creating it...", image_path.to_str().unwrap());
-        create_dir(&image_path)?;
+/// Build all projects and generate the artifact in the specified format. If a kernel binary is specified, the kernel
+/// project isn't built and the binary is copied into the image instead. The generation of the artifact is skipped, if
+/// the cache manifest beside the artifact matches the built files.
+pub(crate) fn build_image(args: &Arguments, config: &Config, projects: Vec<CargoProject>, artifact: &ArtifactArguments,
+                          build_args: &BuildArguments, kernel: Option<&Path>) -> Result<(), Error> {
+    if !config.partitions.is_empty() && artifact.format != ImageFormat::Raw {
+        return Err(Error::InvalidConfig(String::from("Partitions are only supported by the raw image format")));
     }
 
-    let image_path = image_path.join(image_file);
-    let image = Image::new(Path::new(&args.workspace_path).join(&image_path).to_str()
-                               .unwrap(), *block_size, *block_count)?;
+    // The FAT volume supports sectors with 512 to 4096 bytes
+    if !build_args.block_size.is_power_of_two() || !(512..=4096).contains(&build_args.block_size) {
+        return Err(Error::InvalidParameter(String::from("block_size")));
+    }
 
+    info!("Build all in-memory loaded Rust projects");
+    let cargo_path = find_in_path("cargo").ok_or(Error::ExecutableNotFound(String::from("cargo")))?;
+    let signing = build_args.sign_key.as_ref().zip(build_args.sign_cert.as_ref());
+
+    // Build projects and collect the host files with their path in the image
+    let mut files = Vec::new();
     for project in projects.clone() {
         let project_name = project.manifest.package().name();
 
-        // Execute `cargo build`
-        let mut command = Command::new(&cargo_path);
-        command.arg("build")
-            .arg("--package")
-            .arg(project_name);
-        command.current_dir(&args.workspace_path);
+        // Use specified kernel binary instead of building the kernel project
+        if let Some(kernel) = kernel.filter(|_| project.kind == ProjectKind::Kernel) {
+            info!("Use kernel binary {} for {}", kernel.to_str().unwrap().gradient(Color::Cyan),
+                project_name.color(Color::Green));
+            if let Some(protocol) = project.boot_protocol {
+                validate_boot_protocol(kernel, protocol)?;
+            }
 
-        if let Some(target) = project.kind.target(&project, args.target_arch) {
-            command
-                .arg("--target").arg(&target)
-                .arg("-Zbuild-std=core,alloc,compiler_builtins")
-                .arg("-Zbuild-std-features=compiler-builtins-mem");
-            info!("Run build task on {} ({}) with `{}` ({})", project_name.color(Color::Green),
-            project.kind.to_string().color(Color::Orange3), "cargo build".color(Color::Red), target.color(Color::Green));
-        } else {
-            info!("Run build task on {} ({}) with `{}`", project_name.color(Color::Green),
-            project.kind.to_string().color(Color::Orange3), "cargo build".color(Color::Red));
+            let image_path = project.kind.image_target_file(&project, args.target_arch).unwrap();
+            files.push((kernel.to_path_buf(), image_path));
+            continue;
         }
 
-        // Validate exit code
-        let exit_status = command.status()?;
-        if !exit_status.success() {
-            return Err(Error::BuildFailed(String::from(project_name), exit_status.code().unwrap()));
-        }
+        build_project(args, &cargo_path, &project)?;
 
-        // Move file into image
         if let Some(output_path) = project.kind.output_file_path(&project, args.target_arch, project_name) {
-            let image_path = project.kind.image_target_file(&project, args.target_arch, project_name).unwrap();
-            image.copy_into(Path::new(&args.workspace_path).join(output_path), image_path)?;
+            let image_path = project.kind.image_target_file(&project, args.target_arch).unwrap();
+            let output_path = Path::new(&args.workspace_path).join(output_path);
+
+            // Validate the header of the boot protocol, before the kernel is copied into the image
+            if let Some(protocol) = project.boot_protocol {
+                validate_boot_protocol(&output_path, protocol)?;
+            }
+            files.push((output_path, image_path));
         }
     }
 
-    // Create ISO file
-    info!("Generate ISO file");
-    let xorriso_path = find_in_path("xorriso").ok_or(Error::ExecutableNotFound(String::from("cargo")))?;
-    let mut command = Command::new(xorriso_path);
-    command
-        .arg("-as").arg("mkisofs")
-        .arg("-V").arg("EFI_ISO_BOOT")
-        .arg("-e").arg(image_file)
-        .arg("-no-emul-boot")
-        .arg("-o").arg(iso_file)
-        .arg(".image/");
-    command.current_dir(&args.workspace_path);
+    // Skip the generation, if the artifact is generated from the same files and layout. The unsigned files are
+    // hashed, because the signatures aren't reproducible.
+    let workspace_path = Path::new(&args.workspace_path);
+    let artifact_path = match artifact.format {
+        ImageFormat::Iso => workspace_path.join(&artifact.iso_file),
+        ImageFormat::Raw => workspace_path.join(&artifact.image_file),
+        ImageFormat::Directory => workspace_path.join(&artifact.esp_directory)
+    };
 
-    let exit_status = command.status()?;
-    if !exit_status.success() {
-        return Err(Error::ProcessFailed(String::from("xorriso"), exit_status.code().unwrap()));
+    let mut layout_inputs = vec![format!("{:?}", artifact.format), artifact.image_file.clone(),
+                                 artifact.iso_file.clone(), artifact.esp_directory.clone(),
+                                 build_args.block_size.to_string(), build_args.block_count.to_string(),
+                                 format!("{:?}", config.layout), format!("{:?}", config.volume),
+                                 format!("{:?}", config.partitions)];
+    if let Some((key, certificate)) = signing {
+        layout_inputs.push(hash_file(workspace_path.join(key))?);
+        layout_inputs.push(hash_file(workspace_path.join(certificate))?);
+    }
+
+    let mut manifest = CacheManifest::new(&layout_inputs.iter().map(String::as_str).collect::<Vec<_>>());
+    for (host_file, image_file) in &files {
+        manifest.artifacts.insert(image_file.clone(), hash_file(host_file)?);
+    }
+
+    let mut partitions = Vec::new();
+    for partition in &config.partitions {
+        let files = partition_files(workspace_path, partition)?;
+        for (host_file, image_file) in &files {
+            manifest.artifacts.insert(format!("partition:{}/{}", partition.name, image_file), hash_file(host_file)?);
+        }
+        partitions.push((partition, files));
+    }
+
+    let manifest_path = CacheManifest::path(&artifact_path);
+    if manifest.is_up_to_date(&artifact_path) {
+        info!("{} is up to date, skipping generation", artifact_path.to_str().unwrap().gradient(Color::Blue));
+        return Ok(());
+    }
+
+    // Generate image. The image of ISO files is generated in a temporary directory beside the ISO file, because the
+    // directory is packed into the ISO file.
+    let image_name = Path::new(&artifact.image_file).file_name()
+        .ok_or(Error::InvalidParameter(String::from("image_file")))?;
+    let image_path = match artifact.format {
+        ImageFormat::Iso => artifact_path.parent().unwrap().join(".image").join(image_name),
+        ImageFormat::Raw | ImageFormat::Directory => artifact_path.clone()
+    };
+
+    // The EFI system partition is the first partition of the GUID partition table, if partitions are configured.
+    // Otherwise the image only contains the FAT volume.
+    let esp_size = build_args.block_size as u64 * build_args.block_count as u64;
+    let disk = if partitions.is_empty() { None } else { Some(partition_table(esp_size, &config.partitions)?) };
+    let esp_offset = disk.as_ref().map_or(0, |disk| disk.partitions[0].offset());
+
+    // Refuse to overwrite files and directories, which aren't generated by OSImage
+    if artifact.format == ImageFormat::Iso {
+        check_overwrite(&artifact_path, &manifest_path, None, &config.volume, build_args.force)?;
+    }
+
+    // The intermediate image of the ISO file belongs to the manifest of the ISO file, because it isn't cached itself
+    let image_size = disk.as_ref().map_or(esp_size, |disk| disk.size);
+    match artifact.format {
+        ImageFormat::Iso | ImageFormat::Raw => check_overwrite(&image_path, &manifest_path, Some(image_size),
+            &config.volume, build_args.force)?,
+        ImageFormat::Directory => check_overwrite_directory(&image_path, build_args.force)?
+    }
+
+    // Remove the outdated manifest, so an interrupted generation isn't considered as up to date
+    if manifest_path.exists() {
+        remove_file(&manifest_path)?;
+    }
+
+    let mut destination = match artifact.format {
+        ImageFormat::Iso | ImageFormat::Raw => {
+            let image_directory = image_path.parent().unwrap();
+            if !image_directory.exists() {
+                debug!("Directory {} not found! Creating it...", image_directory.to_str().unwrap());
+                create_dir_all(image_directory)?;
+            }
+
+            if let Some(disk) = disk.as_ref() {
+                disk.create(&image_path)?;
+            }
+            Destination::Image(Box::new(FatWriter::new(&image_path, esp_offset, build_args.block_size,
+                build_args.block_count, &config.volume)?))
+        }
+        ImageFormat::Directory => {
+            // Remove the files of the previous build, so no stale files remain in the directory
+            if image_path.exists() {
+                debug!("Remove previous directory {}", image_path.to_str().unwrap());
+                remove_dir_all(&image_path)?;
+            }
+            create_dir_all(&image_path)?;
+            Destination::Directory(image_path.clone())
+        }
+    };
+
+    // Move files into image
+    for (mut host_file, image_file) in files {
+        // Sign EFI binaries for Secure Boot, if key and certificate are specified. Unified kernel images are signed,
+        // if the kernel is built as EFI binary or placed with an `.efi` image path.
+        if let Some((key, certificate)) = signing {
+            let efi_binary = [host_file.as_path(), Path::new(&image_file)].iter()
+                .any(|file| file.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("efi")));
+            if efi_binary {
+                host_file = sign_efi_binary(args, key, certificate, &host_file)?;
+            }
+        }
+        destination.copy_into(host_file, image_file)?;
+    }
+
+    // Unmount the image, so all buffered data is written into the image file
+    if let Destination::Image(writer) = destination {
+        writer.finish()?;
+    }
+
+    // Apply the attributes, timestamps and names of the layout to the directory entries of the image
+    if !config.layout.entries.is_empty() {
+        match artifact.format {
+            ImageFormat::Iso | ImageFormat::Raw => apply_layout(&image_path, esp_offset, &config.layout.entries)?,
+            ImageFormat::Directory => warn!("The layout entries are only applied to images, ignoring them")
+        }
+    }
+
+    // Write the file systems of the additional partitions
+    if let Some(disk) = disk.as_ref() {
+        for ((partition, files), region) in partitions.iter().zip(&disk.partitions[1..]) {
+            write_partition(workspace_path, &image_path, partition, region, files, build_args.keep_intermediate)?;
+        }
+    }
+
+    if artifact.format == ImageFormat::Iso {
+        // Create ISO file
+        info!("Generate ISO file");
+        let xorriso_path = find_in_path("xorriso").ok_or(Error::ExecutableNotFound(String::from("xorriso")))?;
+        let mut command = Command::new(xorriso_path);
+        command
+            .arg("-as").arg("mkisofs")
+            .arg("-V").arg(config.volume.iso_volume_id.as_deref().unwrap_or("EFI_ISO_BOOT"));
+        if let Some(publisher) = config.volume.iso_publisher.as_ref() {
+            command.arg("-publisher").arg(publisher);
+        }
+
+        if let Some(application) = config.volume.iso_application.as_ref() {
+            command.arg("-A").arg(application);
+        }
+        // The paths are already joined with the workspace, so they're passed as absolute paths to xorriso running in
+        // the workspace
+        command
+            .arg("-e").arg(image_name)
+            .arg("-no-emul-boot")
+            .arg("-o").arg(path::absolute(&artifact_path)?)
+            .arg(path::absolute(image_path.parent().unwrap())?);
+        command.current_dir(&args.workspace_path);
+
+        let exit_status = command.status()?;
+        if !exit_status.success() {
+            return Err(Error::ProcessFailed(String::from("xorriso"), exit_status.code().unwrap()));
+        }
+
+        // Remove the intermediate image and the directory, if it contains no other files
+        if build_args.keep_intermediate {
+            info!("Keep intermediate image {}", image_path.to_str().unwrap().gradient(Color::Blue));
+        } else {
+            remove_file(&image_path)?;
+            let image_directory = image_path.parent().unwrap();
+            if read_dir(image_directory)?.next().is_none() {
+                remove_dir(image_directory)?;
+            }
+        }
+    } else {
+        info!("Generated {} at {}", if artifact.format == ImageFormat::Raw { "image" } else { "directory" },
+            image_path.to_str().unwrap().gradient(Color::Blue));
     }
 
-    // Cleanup
-    remove_file(&image_path)?;
-    remove_dir(image_path.parent().unwrap())?;
+    manifest.write(&manifest_path)?;
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,149 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::error::Error;
+
+/// The cache manifest stored beside the generated artifact. It records the hashes of all files copied into the
+/// artifact and of the layout inputs, so the generation is skipped if nothing changed since the last build.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct CacheManifest {
+    /// The hash of the inputs defining the layout of the artifact (format, file names, geometry and signing keys)
+    pub(crate) layout: String,
+    /// The hashes of the host files by their path in the artifact
+    pub(crate) artifacts: BTreeMap<String, String>
+}
+
+impl CacheManifest {
+    /// Create the manifest with the hash of the layout inputs
+    pub(crate) fn new(layout_inputs: &[&str]) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(env!("CARGO_PKG_VERSION"));
+        for input in layout_inputs {
+            hasher.update((input.len() as u64).to_le_bytes());
+            hasher.update(input);
+        }
+
+        CacheManifest {
+            layout: format!("{:x}", hasher.finalize()),
+            artifacts: BTreeMap::new()
+        }
+    }
+
+    /// Return the path of the manifest beside the artifact (e.g. `image.iso.cache.json` for `image.iso`)
+    pub(crate) fn path<P: AsRef<Path>>(artifact: P) -> PathBuf {
+        let artifact = artifact.as_ref();
+        artifact.with_file_name(format!("{}.cache.json", artifact.file_name().unwrap().to_str().unwrap()))
+    }
+
+    /// Read the manifest or return `None`, if the manifest doesn't exist or is invalid
+    pub(crate) fn read<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let manifest = serde_json::from_slice(&fs::read(&path).ok()?);
+        if let Err(error) = manifest.as_ref() {
+            debug!("Ignore invalid cache manifest {} => {}", path.as_ref().to_str().unwrap(), error);
+        }
+        manifest.ok()
+    }
+
+    pub(crate) fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Check whether the artifact exists and the manifest beside the artifact matches this manifest
+    pub(crate) fn is_up_to_date<P: AsRef<Path>>(&self, artifact: P) -> bool {
+        artifact.as_ref().exists() && CacheManifest::read(CacheManifest::path(artifact)).is_some_and(|cached| {
+            cached == *self
+        })
+    }
+}
+
+/// Return the SHA-256 hash of the file content as hexadecimal string
+pub(crate) fn hash_file<P: AsRef<Path>>(path: P) -> Result<String, Error> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let length = file.read(&mut buffer)?;
+        if length == 0 {
+            break;
+        }
+        hasher.update(&buffer[..length]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use crate::tasks::cache::{CacheManifest, hash_file};
+
+    /// Write the artifact and the file copied into it, and create the manifest of the build
+    fn build(directory: &Path, layout_inputs: &[&str]) -> CacheManifest {
+        let mut manifest = CacheManifest::new(layout_inputs);
+        manifest.artifacts.insert(String::from("EFI/BOOT/BOOTX64.EFI"), hash_file(directory.join("kernel")).unwrap());
+        fs::write(directory.join("image.iso"), b"artifact").unwrap();
+        manifest
+    }
+
+    #[test]
+    fn manifest_path_is_beside_artifact() {
+        assert_eq!(CacheManifest::path("target/image.iso"), Path::new("target/image.iso.cache.json"));
+    }
+
+    #[test]
+    fn hash_file_is_sha256() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("file");
+        fs::write(&path, b"abc").unwrap();
+        assert_eq!(hash_file(path).unwrap(), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    }
+
+    #[test]
+    fn manifest_hit() {
+        let directory = tempfile::tempdir().unwrap();
+        let artifact = directory.path().join("image.iso");
+        fs::write(directory.path().join("kernel"), b"kernel").unwrap();
+        build(directory.path(), &["iso", "image.img"]).write(CacheManifest::path(&artifact)).unwrap();
+
+        let manifest = build(directory.path(), &["iso", "image.img"]);
+        assert_eq!(CacheManifest::read(CacheManifest::path(&artifact)), Some(manifest.clone()));
+        assert!(manifest.is_up_to_date(&artifact));
+    }
+
+    #[test]
+    fn manifest_miss() {
+        let directory = tempfile::tempdir().unwrap();
+        let artifact = directory.path().join("image.iso");
+        fs::write(directory.path().join("kernel"), b"kernel").unwrap();
+        let manifest = build(directory.path(), &["iso", "image.img"]);
+        assert!(!manifest.is_up_to_date(&artifact), "Manifest doesn't exist");
+
+        manifest.write(CacheManifest::path(&artifact)).unwrap();
+        assert!(!build(directory.path(), &["raw", "image.img"]).is_up_to_date(&artifact), "Layout changed");
+        assert!(!build(directory.path(), &["is", "oimage.img"]).is_up_to_date(&artifact), "Layout inputs moved");
+
+        fs::write(directory.path().join("kernel"), b"changed kernel").unwrap();
+        assert!(!build(directory.path(), &["iso", "image.img"]).is_up_to_date(&artifact), "File changed");
+
+        fs::write(directory.path().join("kernel"), b"kernel").unwrap();
+        assert!(build(directory.path(), &["iso", "image.img"]).is_up_to_date(&artifact));
+        fs::remove_file(&artifact).unwrap();
+        assert!(!manifest.is_up_to_date(&artifact), "Artifact removed");
+    }
+
+    #[test]
+    fn invalid_manifest_is_ignored() {
+        let directory = tempfile::tempdir().unwrap();
+        let artifact = directory.path().join("image.iso");
+        fs::write(directory.path().join("kernel"), b"kernel").unwrap();
+        fs::write(CacheManifest::path(&artifact), b"{ \"layout\": 1 }").unwrap();
+        assert_eq!(CacheManifest::read(CacheManifest::path(&artifact)), None);
+        assert!(!build(directory.path(), &["iso"]).is_up_to_date(&artifact));
+    }
+}
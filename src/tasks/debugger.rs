@@ -0,0 +1,137 @@
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use clap::ValueEnum;
+use colorful::{Color, Colorful};
+use log::{info, warn};
+use crate::Arguments;
+use crate::error::Error;
+use crate::project::{CargoProject, ProjectKind};
+use crate::utils::{find_in_path, state_directory};
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(ValueEnum, Clone, Copy, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub(crate) enum Debugger {
+    GDB,
+    LLDB
+}
+
+impl Display for Debugger {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(formatter, "{}", match self {
+            Debugger::GDB => "gdb",
+            Debugger::LLDB => "lldb"
+        })
+    }
+}
+
+/// Read the preferred image base out of the optional header of the specified PE file
+fn pe_image_base<P: AsRef<Path>>(path: P) -> Result<u64, Error> {
+    let invalid = || Error::InvalidExecutable(path.as_ref().to_string_lossy().into_owned());
+    let mut file = File::open(&path)?;
+    let mut buffer = [0_u8; 4];
+
+    // Read offset of PE header from DOS header and validate signature
+    file.seek(SeekFrom::Start(0x3C))?;
+    file.read_exact(&mut buffer)?;
+    file.seek(SeekFrom::Start(u32::from_le_bytes(buffer) as u64))?;
+    file.read_exact(&mut buffer)?;
+    if &buffer != b"PE\0\0" {
+        return Err(invalid());
+    }
+
+    // Skip COFF header and read image base by the magic of the optional header (PE32 or PE32+)
+    file.seek(SeekFrom::Current(20))?;
+    let mut magic = [0_u8; 2];
+    file.read_exact(&mut magic)?;
+    match u16::from_le_bytes(magic) {
+        0x10B => {
+            file.seek(SeekFrom::Current(26))?;
+            file.read_exact(&mut buffer)?;
+            Ok(u32::from_le_bytes(buffer) as u64)
+        }
+        0x20B => {
+            let mut buffer = [0_u8; 8];
+            file.seek(SeekFrom::Current(22))?;
+            file.read_exact(&mut buffer)?;
+            Ok(u64::from_le_bytes(buffer))
+        }
+        _ => Err(invalid())
+    }
+}
+
+/// Return the path of the build output of the first project with the specified kind
+pub(crate) fn artifact_path(args: &Arguments, projects: &[CargoProject], kind: ProjectKind) -> Option<PathBuf> {
+    projects.iter().find(|project| project.kind == kind).and_then(|project| {
+        project.kind.output_file_path(project, args.target_arch, project.manifest.package().name())
+            .map(|path| Path::new(&args.workspace_path).join(path))
+    })
+}
+
+/// Write the init script for the specified debugger into the state directory of the workspace. The script sets the
+/// architecture, loads the symbols of the kernel and bootloader (relocated from the preferred PE image base to the
+/// specified load address) and connects to the GDB server of QEMU.
+pub(crate) fn write_debug_script(args: &Arguments, projects: &[CargoProject], debugger: Debugger, debug_port: u16,
+                                 bootloader_load_address: Option<u64>) -> Result<PathBuf, Error> {
+    let mut script = String::new();
+    let kernel = artifact_path(args, projects, ProjectKind::Kernel);
+    let bootloader = artifact_path(args, projects, ProjectKind::Bootloader).filter(|path| path.exists());
+
+    // Calculate the offset between preferred image base and the real load address of the bootloader
+    let mut bootloader_offset = 0;
+    if let (Some(bootloader), Some(load_address)) = (bootloader.as_ref(), bootloader_load_address) {
+        let image_base = pe_image_base(bootloader)?;
+        bootloader_offset = load_address.wrapping_sub(image_base);
+        info!("Relocate bootloader symbols from image base {:#x} to {:#x}", image_base, load_address);
+    }
+
+    match debugger {
+        Debugger::GDB => {
+            script.push_str(&format!("set architecture {}\n", args.target_arch.gdb_architecture()));
+            match kernel.as_ref() {
+                Some(kernel) => script.push_str(&format!("symbol-file {}\n", kernel.to_str().unwrap())),
+                None => warn!("No kernel project found, the debugger is started without kernel symbols")
+            }
+            if let Some(bootloader) = bootloader.as_ref() {
+                script.push_str(&format!("add-symbol-file {} -o {:#x}\n", bootloader.to_str().unwrap(),
+                    bootloader_offset));
+            }
+            script.push_str(&format!("target remote localhost:{}\n", debug_port));
+        }
+        Debugger::LLDB => {
+            match kernel.as_ref() {
+                Some(kernel) => script.push_str(&format!("target create {}\n", kernel.to_str().unwrap())),
+                None => warn!("No kernel project found, the debugger is started without kernel symbols")
+            }
+            if let Some(bootloader) = bootloader.as_ref() {
+                script.push_str(&format!("target modules add {}\n", bootloader.to_str().unwrap()));
+                script.push_str(&format!("target modules load --file {} --slide {:#x}\n",
+                    bootloader.to_str().unwrap(), bootloader_offset));
+            }
+            script.push_str(&format!("gdb-remote localhost:{}\n", debug_port));
+        }
+    }
+
+    let script_path = state_directory(&args.workspace_path)?.join(format!("{}init", debugger));
+    let mut file = fs::OpenOptions::new().write(true).create(true).truncate(true).open(&script_path)?;
+    file.write_all(script.as_bytes())?;
+    info!("Wrote {} init script to {}", debugger.to_string().color(Color::Green),
+        script_path.to_str().unwrap().gradient(Color::Blue));
+    Ok(script_path)
+}
+
+/// Launch the specified debugger with the init script
+pub(crate) fn launch_debugger<P: AsRef<Path>>(debugger: Debugger, script: P) -> Result<Child, Error> {
+    let debugger_path = find_in_path(debugger.to_string())
+        .ok_or(Error::ExecutableNotFound(debugger.to_string()))?;
+
+    let mut command = Command::new(debugger_path);
+    match debugger {
+        Debugger::GDB => command.arg("-x").arg(script.as_ref()),
+        Debugger::LLDB => command.arg("-s").arg(script.as_ref())
+    };
+    Ok(command.spawn()?)
+}
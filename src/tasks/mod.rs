@@ -1,2 +1,9 @@
 pub(crate) mod build;
+pub(crate) mod cache;
+pub(crate) mod debugger;
 pub(crate) mod qemu;
+pub(crate) mod report;
+pub(crate) mod runner;
+pub(crate) mod secureboot;
+pub(crate) mod test;
+pub(crate) mod watch;
@@ -0,0 +1,119 @@
+use std::path::Path;
+use std::process::Command;
+use clap::ValueEnum;
+use log::debug;
+use serde::Deserialize;
+use crate::{Arguments, QemuArguments};
+use crate::config::Config;
+use crate::error::Error;
+use crate::tasks::qemu::profile::{LaunchProfile, Storage};
+
+/// The network backend of the virtual network card
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Network {
+    /// User mode networking (SLIRP), no privileges required
+    User,
+    /// TAP interface of the host (requires a configured TAP interface)
+    Tap,
+    /// No network card
+    None
+}
+
+/// An additional disk attached to the virtual machine, specified as `path[:interface]`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Disk {
+    pub(crate) path: String,
+    pub(crate) interface: Option<Storage>
+}
+
+/// Parse a disk from the command line or config (e.g. `disk.img:nvme`). Without interface, the disk is attached to
+/// the storage interface of the launch profile. The path is only split at the last colon, if the suffix is the name of
+/// an interface, so paths containing colons (e.g. `C:\disk.img`) are kept.
+pub(crate) fn parse_disk(value: &str) -> Result<Disk, String> {
+    let split = value.rsplit_once(':').filter(|(path, _)| !path.is_empty())
+        .and_then(|(path, interface)| Storage::from_str(interface, true).ok().map(|interface| (path, interface)));
+    match split {
+        Some((path, interface)) => Ok(Disk { path: String::from(path), interface: Some(interface) }),
+        None => Ok(Disk { path: String::from(value), interface: None })
+    }
+}
+
+/// Attach the network card, additional disks, USB input devices and raw devices of the command line and config to the
+/// QEMU command. Lists of the command line are appended to the lists of the config.
+pub(crate) fn attach_devices(command: &mut Command, args: &Arguments, config: &Config, qemu_args: &QemuArguments,
+                             profile: &LaunchProfile) -> Result<(), Error> {
+    // Attach network card with the selected backend
+    let network = qemu_args.net.or(config.qemu.net);
+    let nic_model = qemu_args.nic_model.clone().or(config.qemu.nic_model.clone())
+        .unwrap_or(String::from(profile.nic_model));
+    match network {
+        Some(Network::User) => {
+            command.arg("-netdev").arg("user,id=net0");
+            command.arg("-device").arg(format!("{},netdev=net0", nic_model));
+        }
+        Some(Network::Tap) => {
+            let mut netdev = String::from("tap,id=net0,script=no,downscript=no");
+            if let Some(interface) = qemu_args.tap_interface.as_ref().or(config.qemu.tap_interface.as_ref()) {
+                netdev.push_str(&format!(",ifname={}", interface));
+            }
+            command.arg("-netdev").arg(netdev);
+            command.arg("-device").arg(format!("{},netdev=net0", nic_model));
+        }
+        Some(Network::None) => {
+            command.arg("-nic").arg("none");
+        }
+        None => {}
+    }
+
+    // Attach additional disks. The drive format is derived from the file extension.
+    let mut disks = Vec::new();
+    for disk in &config.qemu.disks {
+        disks.push(parse_disk(disk).map_err(|error| Error::InvalidConfig(format!("Invalid disk '{}' => {}", disk,
+            error)))?);
+    }
+    disks.extend(qemu_args.disk.iter().cloned());
+    for (index, disk) in disks.iter().enumerate() {
+        let drive = format!("disk{}", index);
+        let path = Path::new(&args.workspace_path).join(&disk.path);
+        if !path.exists() {
+            return Err(Error::FileNotFound(path.to_str().unwrap().to_owned()));
+        }
+
+        let format = if path.extension().is_some_and(|extension| extension == "qcow2") { "qcow2" } else { "raw" };
+        debug!("Attach disk {} as {} drive", disk.path, format);
+        command.arg("-drive").arg(format!("if=none,id={},format={},file={}", drive, format, path.to_str().unwrap()));
+        disk.interface.unwrap_or(profile.storage).devices(&drive).into_iter()
+            .for_each(|device| { command.arg("-device").arg(device); });
+    }
+
+    // Attach USB keyboard and tablet on a XHCI controller
+    if qemu_args.usb || config.qemu.usb.unwrap_or(false) {
+        command.arg("-device").arg("qemu-xhci,id=usb");
+        command.arg("-device").arg("usb-kbd,bus=usb.0");
+        command.arg("-device").arg("usb-tablet,bus=usb.0");
+    }
+
+    for device in config.qemu.devices.iter().chain(qemu_args.device.iter()) {
+        command.arg("-device").arg(device);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tasks::qemu::devices::parse_disk;
+    use crate::tasks::qemu::profile::Storage;
+
+    #[test]
+    fn parse_disks() {
+        let disk = |value: &str| parse_disk(value).map(|disk| (disk.path, disk.interface)).unwrap();
+        assert_eq!(disk("disk.img:nvme"), (String::from("disk.img"), Some(Storage::Nvme)));
+        assert_eq!(disk("data/disk.img:AHCI"), (String::from("data/disk.img"), Some(Storage::Ahci)));
+        assert_eq!(disk("a:b.img:usb"), (String::from("a:b.img"), Some(Storage::Usb)));
+        assert_eq!(disk("disk.img"), (String::from("disk.img"), None));
+        assert_eq!(disk("C:\\disk.img"), (String::from("C:\\disk.img"), None));
+        assert_eq!(disk("a:b.img"), (String::from("a:b.img"), None));
+        assert_eq!(disk(":nvme"), (String::from(":nvme"), None));
+    }
+}
@@ -0,0 +1,314 @@
+use std::fs;
+use std::path::Path;
+use colorful::{Color, Colorful};
+use log::{info, warn};
+use regex::Regex;
+use crate::error::Error;
+use crate::symbolizer::Symbolizer;
+
+/// The name of the file in the state directory, into which QEMU writes the exception log
+pub(crate) const EXCEPTION_LOG: &str = "exception.log";
+
+/// The mnemonics and names of the x86 exception vectors
+const X86_EXCEPTIONS: [(&str, &str); 32] = [
+    ("#DE", "Divide Error"), ("#DB", "Debug"), ("NMI", "Non-Maskable Interrupt"), ("#BP", "Breakpoint"),
+    ("#OF", "Overflow"), ("#BR", "Bound Range Exceeded"), ("#UD", "Invalid Opcode"), ("#NM", "Device Not Available"),
+    ("#DF", "Double Fault"), ("", "Coprocessor Segment Overrun"), ("#TS", "Invalid TSS"),
+    ("#NP", "Segment Not Present"), ("#SS", "Stack-Segment Fault"), ("#GP", "General Protection Fault"),
+    ("#PF", "Page Fault"), ("", "Reserved"), ("#MF", "x87 Floating-Point Exception"), ("#AC", "Alignment Check"),
+    ("#MC", "Machine Check"), ("#XM", "SIMD Floating-Point Exception"), ("#VE", "Virtualization Exception"),
+    ("#CP", "Control Protection Exception"), ("", "Reserved"), ("", "Reserved"), ("", "Reserved"), ("", "Reserved"),
+    ("", "Reserved"), ("", "Reserved"), ("#HV", "Hypervisor Injection Exception"),
+    ("#VC", "VMM Communication Exception"), ("#SX", "Security Exception"), ("", "Reserved")
+];
+
+/// An exception record of the QEMU interrupt log
+#[derive(Clone, Debug)]
+pub(crate) struct ExceptionRecord {
+    pub(crate) vector: u64,
+    pub(crate) name: String,
+    pub(crate) error_code: Option<u64>,
+    pub(crate) pc: Option<u64>,
+    /// The faulting address (CR2 on x86, FAR on ARM, tval on RISC-V)
+    pub(crate) fault_address: Option<u64>
+}
+
+/// The last CPU state dumped into the log
+#[derive(Clone, Debug, Default)]
+pub(crate) struct CpuState {
+    pub(crate) pc: Option<u64>,
+    pub(crate) cr2: Option<u64>,
+    pub(crate) cr3: Option<u64>
+}
+
+/// The parsed exception log of QEMU (`-d int,cpu_reset`)
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ExceptionLog {
+    pub(crate) records: Vec<ExceptionRecord>,
+    /// The vectors of the last chain of nested exceptions (e.g. page fault -> double fault)
+    pub(crate) chain: Vec<u64>,
+    pub(crate) final_state: CpuState,
+    pub(crate) triple_fault: bool
+}
+
+fn hex(value: &str) -> Option<u64> {
+    u64::from_str_radix(value.trim_start_matches("0x"), 16).ok()
+}
+
+fn x86_name(vector: u64) -> String {
+    match X86_EXCEPTIONS.get(vector as usize) {
+        Some((mnemonic, name)) if !mnemonic.is_empty() => format!("{} {}", mnemonic, name),
+        Some((_, name)) => String::from(*name),
+        None => format!("Interrupt {:#x}", vector)
+    }
+}
+
+/// Check whether the exception raised during the delivery of the previous exception causes a double fault
+fn is_double_fault(old: u64, new: u64) -> bool {
+    let contributory = |vector: u64| matches!(vector, 0x00 | 0x0A..=0x0D);
+    (contributory(old) && contributory(new)) || (old == 0x0E && (contributory(new) || new == 0x0E))
+}
+
+impl ExceptionLog {
+    /// Parse the exception records of x86 (`v=0e e=0002 ... pc=...`), ARM (`Taking exception ...`) and RISC-V
+    /// (`riscv_cpu_do_interrupt: ...`) and the register dumps of the log
+    pub(crate) fn parse(log: &str) -> Self {
+        let x86_record = Regex::new(concat!(r"^\s*\d+: v=([0-9a-f]+) e=([0-9a-f]+) i=(\d+) cpl=\d+ ",
+            r"IP=[0-9a-f]+:[0-9a-f]+ pc=([0-9a-f]+) SP=[0-9a-f]+:[0-9a-f]+(?: CR2=([0-9a-f]+))?")).unwrap();
+        let x86_nested = Regex::new(r"check_exception old: (0x[0-9a-f]+) new (0x[0-9a-f]+)").unwrap();
+        let arm_record = Regex::new(r"Taking exception (\d+) \[([^\]]+)\]").unwrap();
+        let arm_detail = Regex::new(r"^\.\.\.with (ESR|FAR|ELR) 0x(?:[0-9a-f]+/0x)?([0-9a-f]+)").unwrap();
+        let riscv_record = Regex::new(concat!(r"riscv_cpu_do_interrupt: hart:\d+, async:(\d), cause:([0-9a-f]+), ",
+            r"epc:(0x[0-9a-f]+), tval:(0x[0-9a-f]+), desc=(\S+)")).unwrap();
+        let pc_register = Regex::new(r"^(?:RIP|EIP)=([0-9a-f]+)").unwrap();
+        let control_registers = Regex::new(r"^CR0=[0-9a-f]+ CR2=([0-9a-f]+) CR3=([0-9a-f]+)").unwrap();
+
+        let mut exception_log = ExceptionLog::default();
+        for line in log.lines() {
+            if let Some(captures) = x86_record.captures(line) {
+                // Hardware and software interrupts are no exceptions
+                let vector = hex(&captures[1]).unwrap_or_default();
+                if vector >= 32 || &captures[3] != "0" {
+                    continue;
+                }
+
+                exception_log.records.push(ExceptionRecord {
+                    vector,
+                    name: x86_name(vector),
+                    error_code: hex(&captures[2]),
+                    pc: hex(&captures[4]),
+                    fault_address: captures.get(5).and_then(|cr2| hex(cr2.as_str()))
+                });
+            } else if let Some(captures) = x86_nested.captures(line) {
+                // Track the delivered exceptions. A nested exception during the delivery of a double fault is a
+                // triple fault, the other nested exceptions are delivered or escalated to a double fault.
+                let (old, new) = (hex(&captures[1]).unwrap_or_default(), hex(&captures[2]).unwrap_or_default());
+                if old == 0xFFFFFFFF {
+                    exception_log.chain.clear();
+                    exception_log.chain.push(new);
+                } else if old != 0x08 {
+                    exception_log.chain.push(if is_double_fault(old, new) { 0x08 } else { new });
+                }
+            } else if let Some(captures) = arm_record.captures(line) {
+                exception_log.records.push(ExceptionRecord {
+                    vector: captures[1].parse().unwrap_or_default(),
+                    name: captures[2].to_owned(),
+                    error_code: None,
+                    pc: None,
+                    fault_address: None
+                });
+            } else if let Some(captures) = arm_detail.captures(line) {
+                if let Some(record) = exception_log.records.last_mut() {
+                    match &captures[1] {
+                        "ESR" => record.error_code = hex(&captures[2]),
+                        "FAR" => record.fault_address = hex(&captures[2]),
+                        _ => record.pc = hex(&captures[2])
+                    }
+                }
+            } else if let Some(captures) = riscv_record.captures(line) {
+                if &captures[1] == "0" {
+                    exception_log.records.push(ExceptionRecord {
+                        vector: hex(&captures[2]).unwrap_or_default(),
+                        name: captures[5].to_owned(),
+                        error_code: None,
+                        pc: hex(&captures[3]),
+                        fault_address: hex(&captures[4]).filter(|tval| *tval != 0)
+                    });
+                }
+            } else if let Some(captures) = pc_register.captures(line) {
+                exception_log.final_state.pc = hex(&captures[1]);
+            } else if let Some(captures) = control_registers.captures(line) {
+                exception_log.final_state.cr2 = hex(&captures[1]);
+                exception_log.final_state.cr3 = hex(&captures[2]);
+            } else if line.starts_with("Triple fault") {
+                exception_log.triple_fault = true;
+            }
+        }
+        exception_log
+    }
+
+    /// Read and parse the exception log file
+    pub(crate) fn read<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Ok(ExceptionLog::parse(&String::from_utf8_lossy(&fs::read(path)?)))
+    }
+
+    /// Print the summary of the log with the last exception, the nested exception chain and the final CPU state.
+    /// Addresses are symbolized with the symbolizer of the kernel, if available.
+    pub(crate) fn print_summary(&self, symbolizer: Option<&Symbolizer>) {
+        let describe = |address: u64| {
+            match symbolizer.and_then(|symbolizer| symbolizer.describe(address)) {
+                Some(description) => format!("{:#x} => {}", address, description.color(Color::Green)),
+                None => format!("{:#x}", address)
+            }
+        };
+
+        let Some(last) = self.records.last() else {
+            info!("No exceptions were recorded");
+            return;
+        };
+
+        warn!("Exception summary ({} exceptions{}):", self.records.len(),
+            if self.triple_fault { ", triple fault" } else { "" });
+        match last.error_code {
+            Some(error_code) => warn!(" - Last exception: {} (vector {:#04x}, error code {:#x})",
+                last.name.clone().color(Color::Red), last.vector, error_code),
+            None => warn!(" - Last exception: {} (vector {:#04x})", last.name.clone().color(Color::Red), last.vector)
+        }
+
+        if let Some(pc) = last.pc {
+            warn!(" - Faulting instruction: {}", describe(pc));
+        }
+
+        if let Some(fault_address) = last.fault_address {
+            warn!(" - Faulting address{}: {:#x}", if last.name.starts_with("#PF") { " (CR2)" } else { "" },
+                fault_address);
+        }
+
+        if self.chain.len() > 1 {
+            let mut chain: Vec<String> = self.chain.iter().map(|vector| x86_name(*vector)).collect();
+            if self.triple_fault {
+                chain.push(String::from("Triple Fault"));
+            }
+            warn!(" - Exception chain: {}", chain.join(" -> "));
+        }
+
+        if let Some(pc) = self.final_state.pc {
+            warn!(" - Final instruction pointer: {}", describe(pc));
+        }
+
+        if let Some((cr2, cr3)) = self.final_state.cr2.zip(self.final_state.cr3) {
+            warn!(" - Final CR2: {:#x}, CR3: {:#x}", cr2, cr3);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tasks::qemu::exception::ExceptionLog;
+
+    const X86_64_LOG: &str = "\
+SMM: enter
+     0: v=20 e=0000 i=0 cpl=0 IP=0008:ffffffff80000010 pc=ffffffff80000010 SP=0010:ffffffff80100000 \
+env->regs[R_EAX]=0000000000000000
+     1: v=03 e=0000 i=1 cpl=0 IP=0008:ffffffff80000020 pc=ffffffff80000020 SP=0010:ffffffff80100000 \
+env->regs[R_EAX]=0000000000000000
+check_exception old: 0xffffffff new 0xe
+     2: v=0e e=0002 i=0 cpl=0 IP=0008:ffffffff80001234 pc=ffffffff80001234 SP=0010:ffffffff80100000 \
+CR2=0000deadbeef0000
+RAX=0000000000000000 RBX=0000000000000000 RCX=0000000000000000 RDX=0000000000000000
+RIP=ffffffff80001234 RFL=00000046 [---Z-P-] CPL=0 II=0 A20=1 SMM=0 HLT=0
+CR0=80010033 CR2=0000deadbeef0000 CR3=0000000001000000 CR4=00000020
+check_exception old: 0xe new 0xe
+     3: v=08 e=0000 i=0 cpl=0 IP=0008:ffffffff80001234 pc=ffffffff80001234 SP=0010:ffffffff80100000 \
+env->regs[R_EAX]=0000000000000000
+RIP=ffffffff80001234 RFL=00000046 [---Z-P-] CPL=0 II=0 A20=1 SMM=0 HLT=0
+CR0=80010033 CR2=ffffffff80100ff8 CR3=0000000001000000 CR4=00000020
+check_exception old: 0x8 new 0xe
+Triple fault
+CPU Reset (CPU 0)
+";
+
+    #[test]
+    fn parses_x86_triple_fault() {
+        let log = ExceptionLog::parse(X86_64_LOG);
+        assert_eq!(log.records.len(), 2);
+
+        let page_fault = &log.records[0];
+        assert_eq!(page_fault.vector, 0x0E);
+        assert_eq!(page_fault.name, "#PF Page Fault");
+        assert_eq!(page_fault.error_code, Some(2));
+        assert_eq!(page_fault.pc, Some(0xffffffff80001234));
+        assert_eq!(page_fault.fault_address, Some(0xdeadbeef0000));
+
+        let double_fault = &log.records[1];
+        assert_eq!(double_fault.name, "#DF Double Fault");
+        assert_eq!(double_fault.error_code, Some(0));
+        assert_eq!(double_fault.fault_address, None);
+
+        assert_eq!(log.chain, vec![0x0E, 0x08]);
+        assert!(log.triple_fault);
+        assert_eq!(log.final_state.pc, Some(0xffffffff80001234));
+        assert_eq!(log.final_state.cr2, Some(0xffffffff80100ff8));
+        assert_eq!(log.final_state.cr3, Some(0x1000000));
+    }
+
+    #[test]
+    fn tracks_delivered_x86_exceptions() {
+        let log = ExceptionLog::parse("\
+check_exception old: 0xffffffff new 0xd
+check_exception old: 0xffffffff new 0x6
+check_exception old: 0x6 new 0xd
+");
+        assert!(log.records.is_empty());
+        assert_eq!(log.chain, vec![0x06, 0x0D]);
+        assert!(!log.triple_fault);
+
+        let log = ExceptionLog::parse("check_exception old: 0xffffffff new 0xd\ncheck_exception old: 0xd new 0xb\n");
+        assert_eq!(log.chain, vec![0x0D, 0x08]);
+    }
+
+    #[test]
+    fn parses_arm_exceptions() {
+        let log = ExceptionLog::parse("\
+Taking exception 4 [Data Abort] on CPU 0
+...from EL1 to EL1
+...with ESR 0x25/0x96000045
+...with FAR 0xdead0000
+...with ELR 0xffff000040081234
+...to EL1 PC 0xffff000040080a00 PSTATE 0x3c5
+");
+        assert_eq!(log.records.len(), 1);
+        let record = &log.records[0];
+        assert_eq!(record.vector, 4);
+        assert_eq!(record.name, "Data Abort");
+        assert_eq!(record.error_code, Some(0x96000045));
+        assert_eq!(record.fault_address, Some(0xdead0000));
+        assert_eq!(record.pc, Some(0xffff000040081234));
+    }
+
+    #[test]
+    fn parses_riscv_exceptions() {
+        let log = ExceptionLog::parse("\
+riscv_cpu_do_interrupt: hart:0, async:1, cause:0000000000000005, epc:0x0000000080200100, tval:0x0000000000000000, \
+desc=supervisor_timer
+riscv_cpu_do_interrupt: hart:0, async:0, cause:000000000000000d, epc:0x0000000080200abc, tval:0x0000000000001000, \
+desc=load_page_fault
+riscv_cpu_do_interrupt: hart:0, async:0, cause:0000000000000002, epc:0x0000000080200ac0, tval:0x0000000000000000, \
+desc=illegal_instruction
+");
+        assert_eq!(log.records.len(), 2);
+        assert_eq!(log.records[0].vector, 0x0D);
+        assert_eq!(log.records[0].name, "load_page_fault");
+        assert_eq!(log.records[0].pc, Some(0x80200abc));
+        assert_eq!(log.records[0].fault_address, Some(0x1000));
+        assert_eq!(log.records[1].name, "illegal_instruction");
+        assert_eq!(log.records[1].fault_address, None);
+    }
+
+    #[test]
+    fn parses_empty_log() {
+        let log = ExceptionLog::parse("");
+        assert!(log.records.is_empty() && log.chain.is_empty() && !log.triple_fault);
+        assert_eq!(log.final_state.pc, None);
+    }
+}
@@ -0,0 +1,196 @@
+use std::env;
+use std::fmt::{Display, Formatter};
+use std::fs::{copy, metadata, set_permissions};
+use std::path::{Path, PathBuf};
+use clap::ValueEnum;
+use colorful::{Color, Colorful};
+use log::{debug, info};
+use serde::Deserialize;
+use crate::arch::Architecture;
+use crate::error::Error;
+use crate::utils::state_directory;
+
+/// Environment variable with additional directories to search the firmware in (separated like `PATH`)
+pub(crate) const FIRMWARE_PATH_VARIABLE: &str = "OSIMAGE_FIRMWARE_PATH";
+
+/// Common locations of firmware files installed by Linux distributions
+const SYSTEM_FIRMWARE_DIRECTORIES: [&str; 14] = [
+    "/usr/share/OVMF",
+    "/usr/share/ovmf",
+    "/usr/share/ovmf/x64",
+    "/usr/share/edk2/ovmf",
+    "/usr/share/edk2/ovmf-ia32",
+    "/usr/share/edk2/x64",
+    "/usr/share/edk2/ia32",
+    "/usr/share/edk2-ovmf/x64",
+    "/usr/share/AAVMF",
+    "/usr/share/edk2/aarch64",
+    "/usr/share/edk2/arm",
+    "/usr/share/edk2/riscv",
+    "/usr/share/qemu",
+    "/usr/lib/u-boot"
+];
+
+/// The firmware used to boot the image in QEMU
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Firmware {
+    /// EDK2 OVMF for x86 and x86-64, attached as pflash (or as BIOS if only the monolithic image is available)
+    Ovmf,
+    /// EDK2 AAVMF for AArch64, attached as pflash
+    Aavmf,
+    /// EDK2 ArmVirtQemu for 32-bit ARM, attached as pflash
+    Edk2Arm,
+    /// EDK2 RiscVVirt for RISC-V, attached as pflash
+    #[value(name = "edk2-riscv")]
+    #[serde(rename = "edk2-riscv")]
+    Edk2RiscV,
+    /// OpenSBI as BIOS with U-Boot as payload for RISC-V
+    #[value(name = "opensbi-uboot")]
+    #[serde(rename = "opensbi-uboot")]
+    OpenSbiUBoot
+}
+
+impl Display for Firmware {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(formatter, "{}", match self {
+            Firmware::Ovmf => "OVMF",
+            Firmware::Aavmf => "AAVMF",
+            Firmware::Edk2Arm => "EDK2 (ARM)",
+            Firmware::Edk2RiscV => "EDK2 (RISC-V)",
+            Firmware::OpenSbiUBoot => "OpenSBI + U-Boot"
+        })
+    }
+}
+
+/// The located files of a firmware
+#[derive(Clone, Debug)]
+pub(crate) enum FirmwareFiles {
+    /// Monolithic firmware image, attached with `-bios`
+    Monolithic(PathBuf),
+    /// Split firmware code and variable store template, attached as read-only and writable pflash drives
+    Split { code: PathBuf, vars_template: PathBuf },
+    /// Payload started by the default BIOS of QEMU with `-kernel`
+    Payload(PathBuf)
+}
+
+impl Firmware {
+    /// The file names of the split code and variable store files, in the order of preference
+    fn split_files(&self, architecture: Architecture, secure_boot: bool) -> &'static [(&'static str, &'static str)] {
+        match (self, architecture, secure_boot) {
+            (Firmware::Ovmf, Architecture::X86, true) => &[
+                ("OVMF32_CODE_4M.secboot.fd", "OVMF32_VARS_4M.fd"),
+                ("OVMF_CODE.secboot.fd", "OVMF_VARS.fd"),
+                ("edk2-i386-secure-code.fd", "edk2-i386-vars.fd")
+            ],
+            (Firmware::Ovmf, Architecture::X86, false) => &[
+                ("OVMF32_CODE_4M.fd", "OVMF32_VARS_4M.fd"),
+                ("OVMF_CODE.fd", "OVMF_VARS.fd"),
+                ("edk2-i386-code.fd", "edk2-i386-vars.fd")
+            ],
+            (Firmware::Ovmf, _, true) => &[
+                ("OVMF_CODE.secboot.fd", "OVMF_VARS.fd"),
+                ("OVMF_CODE_4M.secboot.fd", "OVMF_VARS_4M.fd"),
+                ("OVMF_CODE.secboot.4m.fd", "OVMF_VARS.4m.fd"),
+                ("edk2-x86_64-secure-code.fd", "edk2-i386-vars.fd")
+            ],
+            (Firmware::Ovmf, _, false) => &[
+                ("OVMF_CODE.fd", "OVMF_VARS.fd"),
+                ("OVMF_CODE_4M.fd", "OVMF_VARS_4M.fd"),
+                ("OVMF_CODE.4m.fd", "OVMF_VARS.4m.fd"),
+                ("edk2-x86_64-code.fd", "edk2-i386-vars.fd")
+            ],
+            (Firmware::Aavmf, _, _) => &[
+                ("AAVMF_CODE.fd", "AAVMF_VARS.fd"),
+                ("QEMU_EFI-pflash.raw", "vars-template-pflash.raw"),
+                ("edk2-aarch64-code.fd", "edk2-arm-vars.fd")
+            ],
+            (Firmware::Edk2Arm, _, _) => &[
+                ("AAVMF32_CODE.fd", "AAVMF32_VARS.fd"),
+                ("edk2-arm-code.fd", "edk2-arm-vars.fd")
+            ],
+            (Firmware::Edk2RiscV, _, _) => &[
+                ("RISCV_VIRT_CODE.fd", "RISCV_VIRT_VARS.fd"),
+                ("edk2-riscv-code.fd", "edk2-riscv-vars.fd")
+            ],
+            (Firmware::OpenSbiUBoot, _, _) => &[]
+        }
+    }
+
+    /// The file names of the monolithic image or payload, in the order of preference
+    fn single_files(&self, architecture: Architecture) -> &'static [&'static str] {
+        match (self, architecture) {
+            (Firmware::Ovmf, Architecture::X86) => &["OVMF32.fd", "OVMF.fd"],
+            (Firmware::Ovmf, _) => &["OVMF.fd"],
+            (Firmware::OpenSbiUBoot, Architecture::RISCV32) => &["u-boot.bin", "qemu-riscv32_smode/u-boot.bin"],
+            (Firmware::OpenSbiUBoot, _) => &["u-boot.bin", "qemu-riscv64_smode/u-boot.bin"],
+            _ => &[]
+        }
+    }
+
+    /// Locate the firmware files. The configured firmware path, the directories of the `OSIMAGE_FIRMWARE_PATH`
+    /// variable, the workspace and the common locations of Linux distributions are searched in this order. Split
+    /// firmware is preferred over monolithic firmware in each directory.
+    pub(crate) fn locate<P: AsRef<Path>>(&self, architecture: Architecture, workspace_path: P,
+                                         firmware_path: Option<&String>,
+                                         secure_boot: bool) -> Result<FirmwareFiles, Error> {
+        let mut directories = Vec::new();
+        if let Some(firmware_path) = firmware_path {
+            directories.push(workspace_path.as_ref().join(firmware_path));
+        }
+        if let Some(paths) = env::var_os(FIRMWARE_PATH_VARIABLE) {
+            directories.extend(env::split_paths(&paths));
+        }
+        directories.push(workspace_path.as_ref().to_path_buf());
+        directories.extend(SYSTEM_FIRMWARE_DIRECTORIES.iter().map(PathBuf::from));
+
+        for directory in directories {
+            debug!("Search {} firmware in {}", self, directory.to_str().unwrap());
+            for (code, vars_template) in self.split_files(architecture, secure_boot) {
+                let (code, vars_template) = (directory.join(code), directory.join(vars_template));
+                if code.is_file() && vars_template.is_file() {
+                    return Ok(FirmwareFiles::Split { code, vars_template });
+                }
+            }
+
+            // Secure Boot requires the variable store, so monolithic images can't be used
+            if secure_boot {
+                continue;
+            }
+
+            for file in self.single_files(architecture) {
+                let file = directory.join(file);
+                if file.is_file() {
+                    return Ok(match self {
+                        Firmware::OpenSbiUBoot => FirmwareFiles::Payload(file),
+                        _ => FirmwareFiles::Monolithic(file)
+                    });
+                }
+            }
+        }
+        Err(Error::FirmwareNotFound(if secure_boot { format!("{} (Secure Boot)", self) } else { self.to_string() }))
+    }
+}
+
+/// Return the writable variable store of the workspace. The store is copied from the template into the state
+/// directory, if it doesn't exist or a reset is requested, so the NVRAM boot entries persist across runs.
+pub(crate) fn variable_store<P: AsRef<Path>, T: AsRef<Path>>(workspace_path: P, architecture: Architecture,
+                                                             vars_template: T, reset: bool) -> Result<PathBuf, Error> {
+    let vars_template = vars_template.as_ref();
+    let variable_store = state_directory(workspace_path)?.join(format!("{}-{}", architecture,
+        vars_template.file_name().unwrap().to_str().unwrap()));
+    if reset || !variable_store.exists() {
+        info!("Copy variable store template {} to {}", vars_template.to_str().unwrap().gradient(Color::Cyan),
+            variable_store.to_str().unwrap().gradient(Color::Blue));
+        copy(vars_template, &variable_store)?;
+
+        // Templates of read-only system locations keep their permissions while copying
+        let mut permissions = metadata(&variable_store)?.permissions();
+        if permissions.readonly() {
+            #[allow(clippy::permissions_set_readonly_false)]
+            permissions.set_readonly(false);
+            set_permissions(&variable_store, permissions)?;
+        }
+    }
+    Ok(variable_store)
+}
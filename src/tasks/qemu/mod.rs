@@ -0,0 +1,334 @@
+use std::fs::OpenOptions;
+use std::path;
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+use colorful::{Color, Colorful};
+use log::{debug, info, warn};
+use regex::Regex;
+use crate::{Arguments, ArtifactArguments, ImageFormat, QemuArguments};
+use crate::arch::Architecture;
+use crate::config::Config;
+use crate::error::Error;
+use crate::project::{CargoProject, ProjectKind};
+use crate::symbolizer::Symbolizer;
+use crate::tasks::debugger::{artifact_path, launch_debugger, write_debug_script};
+use crate::tasks::qemu::devices::attach_devices;
+use crate::tasks::qemu::exception::{EXCEPTION_LOG, ExceptionLog};
+use crate::tasks::qemu::firmware::{Firmware, FirmwareFiles, variable_store};
+use crate::tasks::qemu::profile::{BootMedia, LaunchProfile};
+use crate::tasks::qemu::qmp::{QmpClient, check_qmp_socket, qmp_socket_path};
+use crate::tasks::qemu::serial::{SerialMonitor, SerialOutcome};
+use crate::utils::{find_in_path, parse_size, state_directory};
+
+pub(crate) mod devices;
+pub(crate) mod exception;
+pub(crate) mod firmware;
+pub(crate) mod profile;
+pub(crate) mod qmp;
+pub(crate) mod serial;
+
+/// Check whether the target architecture can be virtualized by KVM on this system
+fn is_kvm_available(architecture: Architecture) -> bool {
+    let system = Architecture::system();
+    let compatible = system == architecture || (system == Architecture::X86_64 && architecture == Architecture::X86)
+        || (system == Architecture::ARM64 && architecture == Architecture::ARM);
+    compatible && OpenOptions::new().read(true).write(true).open("/dev/kvm").is_ok()
+}
+
+/// Attach the firmware with the variable store (or the Secure Boot firmware with the enrolled variable store)
+fn attach_firmware(command: &mut Command, args: &Arguments, config: &Config, qemu_args: &QemuArguments, machine: &str,
+                   firmware: Firmware) -> Result<(), Error> {
+    let firmware_path = qemu_args.firmware_path.as_ref().or(config.qemu.firmware_path.as_ref());
+    let secure_boot = qemu_args.secure_boot_vars.is_some();
+    let firmware_files = firmware.locate(args.target_arch, &args.workspace_path, firmware_path, secure_boot)?;
+    if let Some(secure_boot_vars) = &qemu_args.secure_boot_vars {
+        let FirmwareFiles::Split { code, .. } = &firmware_files else {
+            return Err(Error::InvalidParameter(String::from("secure_boot_vars")));
+        };
+
+        // Secure Boot requires the enrolled variable store as pflash drive. OVMF on x86 additionally requires SMM
+        // and the secure pflash, which are only supported by the Q35 machine.
+        debug!("QEMU Secure Boot enabled, set firmware arguments");
+        let x86 = matches!(args.target_arch, Architecture::X86 | Architecture::X86_64);
+        if x86 && machine.contains("q35") {
+            command.arg("-machine").arg(format!("{},smm=on", machine));
+            command.arg("-global").arg("driver=cfi.pflash01,property=secure,value=on");
+        } else {
+            if x86 {
+                warn!("Machine {} doesn't support SMM, Secure Boot requires the q35 machine", machine);
+            }
+            command.arg("-machine").arg(machine);
+        }
+        command.arg("-drive").arg(format!("if=pflash,format=raw,unit=0,readonly=on,file={}", code.to_str().unwrap()));
+        command.arg("-drive").arg(format!("if=pflash,format=raw,unit=1,file={}",
+            Path::new(&args.workspace_path).join(secure_boot_vars).to_str().unwrap()));
+    } else {
+        command.arg("-machine").arg(machine);
+        match &firmware_files {
+            FirmwareFiles::Monolithic(file) => {
+                command.arg("-bios").arg(file);
+            }
+            FirmwareFiles::Split { code, vars_template } => {
+                let vars = variable_store(&args.workspace_path, args.target_arch, vars_template,
+                                          qemu_args.reset_nvram)?;
+                command.arg("-drive").arg(format!("if=pflash,format=raw,unit=0,readonly=on,file={}",
+                    code.to_str().unwrap()));
+                command.arg("-drive").arg(format!("if=pflash,format=raw,unit=1,file={}", vars.to_str().unwrap()));
+            }
+            FirmwareFiles::Payload(file) => {
+                command.arg("-bios").arg("default");
+                command.arg("-kernel").arg(file);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Attach the boot media of the artifact. Raw images and directories are attached to the selected storage interface,
+/// ISO files as CD-ROM.
+fn attach_boot_media(command: &mut Command, args: &Arguments, artifact: &ArtifactArguments, qemu_args: &QemuArguments,
+                     profile: &LaunchProfile) {
+    let storage = qemu_args.storage.unwrap_or(profile.storage);
+    let iso_path = Path::new(&args.workspace_path).join(&artifact.iso_file);
+    match (artifact.format, profile.boot_media) {
+        (ImageFormat::Raw, _) => {
+            command.arg("-drive").arg(format!("if=none,id=boot,format=raw,file={}",
+                Path::new(&args.workspace_path).join(&artifact.image_file).to_str().unwrap()));
+            storage.devices("boot").into_iter().for_each(|device| { command.arg("-device").arg(device); });
+        }
+        (ImageFormat::Directory, _) => {
+            command.arg("-drive").arg(format!("if=none,id=boot,format=raw,file=fat:rw:{}",
+                Path::new(&args.workspace_path).join(&artifact.esp_directory).to_str().unwrap()));
+            storage.devices("boot").into_iter().for_each(|device| { command.arg("-device").arg(device); });
+        }
+        (ImageFormat::Iso, BootMedia::Cdrom) => {
+            command.arg("-cdrom").arg(iso_path.to_str().unwrap());
+        }
+        (ImageFormat::Iso, BootMedia::VirtioScsiCdrom) => {
+            command.arg("-drive").arg(format!("if=none,id=boot,format=raw,media=cdrom,readonly=on,file={}",
+                iso_path.to_str().unwrap()));
+            command.arg("-device").arg("virtio-scsi-pci,id=scsi");
+            command.arg("-device").arg("scsi-cd,drive=boot");
+        }
+        (ImageFormat::Iso, BootMedia::VirtioBlk) => {
+            command.arg("-drive").arg(format!("if=none,id=boot,format=raw,readonly=on,file={}",
+                iso_path.to_str().unwrap()));
+            command.arg("-device").arg("virtio-blk-pci,drive=boot");
+        }
+    }
+}
+
+/// The memory size of the virtual machine in megabytes, merged from command line and config (default: 512). Sizes
+/// without unit are megabytes like in QEMU.
+pub(crate) fn memory_megabytes(qemu_args: &QemuArguments, config: &Config) -> Result<u64, Error> {
+    let memory = qemu_args.memory.as_deref().or(config.qemu.memory.as_deref()).unwrap_or("512").trim();
+    let size = match memory.bytes().all(|character| character.is_ascii_digit()) {
+        true => parse_size(&format!("{}M", memory)),
+        false => parse_size(memory)
+    };
+    size.ok().filter(|size| size % (1024 * 1024) == 0).map(|size| size / (1024 * 1024))
+        .ok_or(Error::InvalidParameter(format!("memory={}", memory)))
+}
+
+/// Create the QEMU command with firmware, boot media, machine configuration and accelerator of the specified arguments.
+/// With direct boot, the specified kernel binary is booted without firmware and boot media.
+pub(crate) fn qemu_command(args: &Arguments, config: &Config, artifact: &ArtifactArguments,
+                           qemu_args: &QemuArguments, kernel: Option<&Path>) -> Result<Command, Error> {
+    let profile = LaunchProfile::of(args.target_arch);
+    let qemu_system = format!("qemu-system-{}", profile.qemu_system);
+    let qemu_path = find_in_path(&qemu_system).ok_or(Error::ExecutableNotFound(qemu_system.clone()))?;
+
+    // Merge machine configuration of command line, config and the launch profile of the architecture. Command line
+    // arguments have precedence.
+    let memory = memory_megabytes(qemu_args, config)?;
+    let smp = qemu_args.smp.or(config.qemu.smp);
+    let machine = qemu_args.machine.clone().or(config.qemu.machine.clone())
+        .unwrap_or(String::from(profile.machine));
+    let cpu = qemu_args.cpu.clone().or(config.qemu.cpu.clone()).or(profile.cpu.map(String::from));
+    let firmware = qemu_args.firmware.or(config.qemu.firmware).unwrap_or(profile.firmware);
+    debug!("Launch {} with machine {} and {} firmware", qemu_system, machine, firmware);
+    let kvm = if qemu_args.kvm { true } else if qemu_args.no_kvm { false } else { config.qemu.kvm.unwrap_or(false) };
+
+    let mut command = Command::new(qemu_path);
+    if qemu_args.direct {
+        // Boot the kernel binary directly without firmware and boot media
+        let kernel = kernel.ok_or(Error::FileNotFound(String::from("kernel binary")))?;
+        debug!("Boot kernel {} directly", kernel.to_str().unwrap());
+        command.arg("-machine").arg(&machine);
+        command.arg("-kernel").arg(kernel);
+        if let Some(initrd) = qemu_args.initrd.as_ref() {
+            command.arg("-initrd").arg(Path::new(&args.workspace_path).join(initrd));
+        }
+
+        if let Some(append) = qemu_args.append.as_ref() {
+            command.arg("-append").arg(append);
+        }
+    } else {
+        attach_firmware(&mut command, args, config, qemu_args, &machine, firmware)?;
+        attach_boot_media(&mut command, args, artifact, qemu_args, &profile);
+    }
+
+    attach_devices(&mut command, args, config, qemu_args, &profile)?;
+    command.arg("-m").arg(memory.to_string());
+
+    if let Some(smp) = smp {
+        command.arg("-smp").arg(smp.to_string());
+    }
+
+    if let Some(cpu) = cpu.as_ref() {
+        command.arg("-cpu").arg(cpu);
+    }
+
+    // Select accelerator. KVM falls back to TCG if not available for the target architecture. The exception info
+    // is only logged by TCG, so KVM is disabled if exception info is requested.
+    if kvm && qemu_args.exception_info {
+        warn!("KVM doesn't support exception info logging, falling back to TCG");
+        command.arg("-accel").arg("tcg");
+    } else if kvm && is_kvm_available(args.target_arch) {
+        debug!("KVM is available, set accelerator arguments");
+        command.arg("-accel").arg("kvm");
+    } else if kvm {
+        warn!("KVM is not available for {} on this system, falling back to TCG", args.target_arch);
+        command.arg("-accel").arg("tcg");
+    }
+
+    if qemu_args.exception_info {
+        debug!("QEMU Exception Info enabled, set arguments");
+        let log_file = state_directory(&args.workspace_path)?.join(EXCEPTION_LOG);
+        command.arg("-d").arg("int,cpu_reset");
+        command.arg("-D").arg(&log_file);
+        command.arg("-no-reboot");
+        info!("Write exception log to {}", log_file.to_str().unwrap().gradient(Color::Blue));
+    }
+
+    if qemu_args.headless {
+        debug!("QEMU Headless mode enabled, disable display and redirect serial output to stdio");
+        command.arg("-display").arg("none");
+        command.arg("-serial").arg("stdio");
+    }
+    Ok(command)
+}
+
+/// Create the monitor of the serial output with the patterns, log file and timeout of the specified arguments. If
+/// symbolization is enabled, the monitor symbolizes the serial output against the kernel binary.
+pub(crate) fn serial_monitor(args: &Arguments, qemu_args: &QemuArguments,
+                             kernel: Option<&Path>) -> Result<SerialMonitor, Error> {
+    let symbolizer = match kernel.filter(|_| qemu_args.symbolize) {
+        Some(kernel) => Some(Symbolizer::load(kernel)?),
+        None if qemu_args.symbolize => {
+            warn!("No kernel binary found, serial output isn't symbolized");
+            None
+        }
+        None => None
+    };
+
+    Ok(SerialMonitor {
+        expect: qemu_args.expect.as_deref().map(Regex::new).transpose()?,
+        fail_on: qemu_args.fail_on.as_deref().map(Regex::new).transpose()?,
+        log_file: qemu_args.serial_log.as_ref().map(|log_file| Path::new(&args.workspace_path).join(log_file)),
+        timeout: qemu_args.timeout.map(Duration::from_secs),
+        symbolizer
+    })
+}
+
+/// Connect to QMP before QEMU is stopped by the serial monitor. The registers are logged if the failure pattern
+/// matched, then the screendump and snapshot are created if requested.
+fn before_stop(args: &Arguments, qemu_args: &QemuArguments, socket: &Path,
+               outcome: &SerialOutcome) -> Result<(), Error> {
+    let failed = matches!(outcome, SerialOutcome::FailurePattern(_));
+    if !failed && qemu_args.screendump_on_exit.is_none() && qemu_args.savevm.is_none() {
+        return Ok(());
+    }
+
+    let mut client = QmpClient::connect(socket, Duration::from_secs(5))?;
+    if failed {
+        info!("Register state after failure:\n{}", client.registers()?);
+    }
+
+    if let Some(file) = qemu_args.screendump_on_exit.as_ref() {
+        let file = path::absolute(Path::new(&args.workspace_path).join(file))?;
+        client.screendump(&file)?;
+        info!("Wrote screendump to {}", file.to_str().unwrap().gradient(Color::Blue));
+    }
+
+    if let Some(name) = qemu_args.savevm.as_ref() {
+        client.save_snapshot(name)?;
+        info!("Saved snapshot {}", name.clone().color(Color::Green));
+    }
+    Ok(())
+}
+
+/// Parse the exception log of the last run and print the summary with the addresses symbolized against the kernel
+fn print_exception_summary(args: &Arguments, kernel: Option<&Path>) -> Result<(), Error> {
+    let log = ExceptionLog::read(state_directory(&args.workspace_path)?.join(EXCEPTION_LOG))?;
+    let symbolizer = kernel.and_then(|kernel| match Symbolizer::load(kernel) {
+            Ok(symbolizer) => Some(symbolizer),
+            Err(error) => {
+                warn!("Unable to symbolize addresses => {}", error);
+                None
+            }
+        });
+    log.print_summary(symbolizer.as_ref());
+    Ok(())
+}
+
+/// Run the artifact in QEMU. The specified kernel binary (or the build output of the kernel project) is used to
+/// symbolize the serial output and exception log.
+pub(crate) fn run_qemu(args: &Arguments, config: &Config, projects: &[CargoProject], artifact: &ArtifactArguments,
+                       qemu_args: &QemuArguments, kernel: Option<&Path>) -> Result<(), Error> {
+    let kernel = kernel.map(Path::to_path_buf).or_else(|| artifact_path(args, projects, ProjectKind::Kernel))
+        .filter(|kernel| kernel.exists());
+    let mut command = qemu_command(args, config, artifact, qemu_args, kernel.as_deref())?;
+
+    // Start QMP server, so the running instance can be controlled by the tool
+    let qmp_socket = qmp_socket_path(&args.workspace_path)?;
+    check_qmp_socket(&qmp_socket)?;
+    command.arg("-qmp").arg(format!("unix:{},server=on,wait=off", qmp_socket.to_str().unwrap()));
+    if let Some(name) = qemu_args.loadvm.as_ref() {
+        command.arg("-loadvm").arg(name);
+    }
+    let mut debug_script = None;
+    if qemu_args.debugging {
+        debug!("QEMU Debugging enabled, set debug arguments");
+        command.arg("-gdb").arg(format!("tcp::{}", qemu_args.debug_port));
+        command.arg("-S");
+        debug_script = Some(write_debug_script(args, projects, qemu_args.debugger, qemu_args.debug_port,
+                                               qemu_args.bootloader_load_address)?);
+    }
+
+    // Start QEMU and attach debugger if wanted. QEMU is stopped after the debugger exits.
+    if let Some(script) = debug_script.filter(|_| qemu_args.attach_debugger) {
+        let mut child = command.spawn()?;
+        let debugger_status = launch_debugger(qemu_args.debugger, script).and_then(|mut debugger| Ok(debugger.wait()?));
+        child.kill()?;
+        child.wait()?;
+        debugger_status?;
+        return Ok(());
+    }
+
+    // Monitor serial output, if patterns, log file or timeout are specified. Headless mode already redirects the
+    // serial output to stdio.
+    let monitor = serial_monitor(args, qemu_args, kernel.as_deref())?;
+    if monitor.is_active() && !qemu_args.headless {
+        command.arg("-serial").arg("stdio");
+    }
+
+    let mut stopped = false;
+    let result = monitor.supervise("QEMU", &mut command, |outcome| {
+        stopped = true;
+        if let Err(error) = before_stop(args, qemu_args, &qmp_socket, outcome) {
+            warn!("Unable to control QEMU before stop => {}", error);
+        }
+    });
+
+    // The screendump and snapshot are only created, if QEMU is stopped by the monitor
+    if !stopped && (qemu_args.screendump_on_exit.is_some() || qemu_args.savevm.is_some()) {
+        warn!("QEMU exited before it was stopped by a pattern or the timeout, no screendump or snapshot was created");
+    }
+
+    if qemu_args.exception_info {
+        print_exception_summary(args, kernel.as_deref())?;
+    }
+    result
+}
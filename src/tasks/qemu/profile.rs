@@ -0,0 +1,114 @@
+use clap::ValueEnum;
+use crate::arch::Architecture;
+use crate::tasks::qemu::firmware::Firmware;
+
+/// How the boot media is attached to the virtual machine
+#[derive(Clone, Copy, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub(crate) enum BootMedia {
+    /// IDE CD-ROM drive (only available on PC machines)
+    Cdrom,
+    /// CD-ROM drive on a virtio SCSI controller
+    VirtioScsiCdrom,
+    /// virtio block device
+    VirtioBlk
+}
+
+/// The storage interface raw images and directories are attached to
+#[derive(ValueEnum, Clone, Copy, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub(crate) enum Storage {
+    /// virtio block device
+    Virtio,
+    /// SATA disk on an AHCI controller
+    Ahci,
+    /// NVMe controller
+    Nvme,
+    /// USB mass storage device on a XHCI controller
+    Usb
+}
+
+impl Storage {
+    /// The QEMU devices attaching the drive with the specified ID with this interface. Each drive gets its own
+    /// controller.
+    pub(crate) fn devices(&self, drive: &str) -> Vec<String> {
+        match self {
+            Storage::Virtio => vec![format!("virtio-blk-pci,drive={}", drive)],
+            Storage::Ahci => vec![format!("ahci,id={}-ahci", drive), format!("ide-hd,drive={0},bus={0}-ahci.0", drive)],
+            Storage::Nvme => vec![format!("nvme,serial={0},drive={0}", drive)],
+            Storage::Usb => vec![format!("qemu-xhci,id={}-xhci", drive), format!("usb-storage,bus={0}-xhci.0,drive={0}",
+                drive)]
+        }
+    }
+}
+
+/// The launch profile of QEMU for an architecture
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct LaunchProfile {
+    pub(crate) qemu_system: &'static str,
+    pub(crate) machine: &'static str,
+    pub(crate) cpu: Option<&'static str>,
+    pub(crate) firmware: Firmware,
+    pub(crate) boot_media: BootMedia,
+    pub(crate) storage: Storage,
+    pub(crate) nic_model: &'static str
+}
+
+impl LaunchProfile {
+    pub(crate) fn of(architecture: Architecture) -> LaunchProfile {
+        match architecture {
+            Architecture::X86_64 => LaunchProfile {
+                qemu_system: "x86_64",
+                machine: "q35",
+                cpu: None,
+                firmware: Firmware::Ovmf,
+                boot_media: BootMedia::Cdrom,
+                storage: Storage::Ahci,
+                nic_model: "e1000"
+            },
+            Architecture::X86 => LaunchProfile {
+                qemu_system: "i386",
+                machine: "q35",
+                cpu: None,
+                firmware: Firmware::Ovmf,
+                boot_media: BootMedia::Cdrom,
+                storage: Storage::Ahci,
+                nic_model: "e1000"
+            },
+            Architecture::ARM64 => LaunchProfile {
+                qemu_system: "aarch64",
+                machine: "virt",
+                cpu: Some("cortex-a72"),
+                firmware: Firmware::Aavmf,
+                boot_media: BootMedia::VirtioScsiCdrom,
+                storage: Storage::Virtio,
+                nic_model: "virtio-net-pci"
+            },
+            Architecture::ARM => LaunchProfile {
+                qemu_system: "arm",
+                machine: "virt",
+                cpu: Some("cortex-a15"),
+                firmware: Firmware::Edk2Arm,
+                boot_media: BootMedia::VirtioScsiCdrom,
+                storage: Storage::Virtio,
+                nic_model: "virtio-net-pci"
+            },
+            Architecture::RISCV64 => LaunchProfile {
+                qemu_system: "riscv64",
+                machine: "virt",
+                cpu: None,
+                firmware: Firmware::OpenSbiUBoot,
+                boot_media: BootMedia::VirtioBlk,
+                storage: Storage::Virtio,
+                nic_model: "virtio-net-pci"
+            },
+            Architecture::RISCV32 => LaunchProfile {
+                qemu_system: "riscv32",
+                machine: "virt",
+                cpu: None,
+                firmware: Firmware::OpenSbiUBoot,
+                boot_media: BootMedia::VirtioBlk,
+                storage: Storage::Virtio,
+                nic_model: "virtio-net-pci"
+            }
+        }
+    }
+}
@@ -0,0 +1,361 @@
+use std::fs::remove_file;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use clap::Subcommand;
+use colorful::{Color, Colorful};
+use log::{debug, info};
+use serde_json::{json, Value};
+use crate::Arguments;
+use crate::error::Error;
+use crate::utils::state_directory;
+
+/// The name of the QMP socket in the state directory of the workspace
+const QMP_SOCKET: &str = "qmp.sock";
+
+/// Return the path of the QMP socket of the workspace
+pub(crate) fn qmp_socket_path<P: AsRef<Path>>(workspace_path: P) -> Result<PathBuf, Error> {
+    Ok(state_directory(workspace_path)?.join(QMP_SOCKET))
+}
+
+/// Check that no other QEMU instance of the workspace listens on the QMP socket, because the socket is shared by all
+/// instances. A stale socket of a stopped instance is removed.
+pub(crate) fn check_qmp_socket<P: AsRef<Path>>(socket: P) -> Result<(), Error> {
+    let socket = socket.as_ref();
+    if !socket.exists() {
+        return Ok(());
+    }
+
+    if UnixStream::connect(socket).is_ok() {
+        return Err(Error::InstanceRunning(socket.to_str().unwrap().to_owned()));
+    }
+    debug!("Remove stale QMP socket {}", socket.to_str().unwrap());
+    remove_file(socket)?;
+    Ok(())
+}
+
+/// Client of the QEMU Machine Protocol. The client is generic over the stream, so it can be used with the socket of
+/// QEMU or any other stream implementing the protocol.
+pub(crate) struct QmpClient<S: Read + Write> {
+    reader: BufReader<S>
+}
+
+impl QmpClient<UnixStream> {
+    /// Connect to the QMP socket. The connection is retried until the socket is created by QEMU or the timeout elapses.
+    pub(crate) fn connect<P: AsRef<Path>>(socket: P, timeout: Duration) -> Result<Self, Error> {
+        let mut waited = Duration::ZERO;
+        loop {
+            match UnixStream::connect(&socket) {
+                Ok(stream) => return QmpClient::new(stream),
+                Err(error) if waited >= timeout => return Err(error.into()),
+                Err(_) => {
+                    thread::sleep(Duration::from_millis(100));
+                    waited += Duration::from_millis(100);
+                }
+            }
+        }
+    }
+}
+
+impl<S: Read + Write> QmpClient<S> {
+    /// Create the client with the stream, read the greeting of the server and negotiate the capabilities
+    pub(crate) fn new(stream: S) -> Result<Self, Error> {
+        let mut client = QmpClient { reader: BufReader::new(stream) };
+        let greeting = client.read_message()?;
+        if greeting.get("QMP").is_none() {
+            return Err(Error::QmpError(format!("Invalid greeting '{}'", greeting)));
+        }
+
+        client.execute("qmp_capabilities", None)?;
+        Ok(client)
+    }
+
+    fn read_message(&mut self) -> Result<Value, Error> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(Error::QmpError(String::from("Connection closed by QEMU")));
+        }
+        Ok(serde_json::from_str(&line)?)
+    }
+
+    /// Execute the command with the arguments and return the result. Asynchronous events received before the result
+    /// are skipped.
+    pub(crate) fn execute(&mut self, command: &str, arguments: Option<Value>) -> Result<Value, Error> {
+        let mut message = json!({ "execute": command });
+        if let Some(arguments) = arguments {
+            message["arguments"] = arguments;
+        }
+
+        debug!("Send QMP command {}", message);
+        let stream = self.reader.get_mut();
+        stream.write_all(format!("{}\n", message).as_bytes())?;
+        stream.flush()?;
+
+        loop {
+            let mut response = self.read_message()?;
+            if let Some(event) = response.get("event") {
+                debug!("Skip QMP event {}", event);
+                continue;
+            }
+
+            if let Some(error) = response.get("error") {
+                let description = error["desc"].as_str().unwrap_or("Unknown error");
+                return Err(Error::QmpError(format!("Command '{}' failed => {}", command, description)));
+            }
+            return Ok(response["return"].take());
+        }
+    }
+
+    /// Execute the command of the human monitor and return the output. This is used for commands without QMP
+    /// equivalent (e.g. `savevm` and `info registers`).
+    pub(crate) fn human_monitor_command(&mut self, command: &str) -> Result<String, Error> {
+        let output = self.execute("human-monitor-command", Some(json!({ "command-line": command })))?;
+        Ok(output.as_str().unwrap_or_default().to_owned())
+    }
+
+    /// Write the content of the display into the file (PPM, or PNG if supported by QEMU and the file extension)
+    pub(crate) fn screendump<P: AsRef<Path>>(&mut self, file: P) -> Result<(), Error> {
+        let file = file.as_ref();
+        let mut arguments = json!({ "filename": file.to_str().unwrap() });
+        if file.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("png")) {
+            arguments["format"] = json!("png");
+        }
+        self.execute("screendump", Some(arguments))?;
+        Ok(())
+    }
+
+    /// Send the key combination (e.g. `ctrl-alt-delete`) to the virtual machine. The keys are QEMU key codes.
+    pub(crate) fn send_keys(&mut self, combination: &str, hold_time: Option<u32>) -> Result<(), Error> {
+        let keys: Vec<Value> = combination.split('-')
+            .map(|key| json!({ "type": "qcode", "data": key.to_lowercase() }))
+            .collect();
+        let mut arguments = json!({ "keys": keys });
+        if let Some(hold_time) = hold_time {
+            arguments["hold-time"] = json!(hold_time);
+        }
+        self.execute("send-key", Some(arguments))?;
+        Ok(())
+    }
+
+    /// Save a snapshot of the virtual machine with the specified name
+    pub(crate) fn save_snapshot(&mut self, name: &str) -> Result<(), Error> {
+        let output = self.human_monitor_command(&format!("savevm {}", name))?;
+        if !output.trim().is_empty() {
+            return Err(Error::QmpError(format!("Unable to save snapshot '{}' => {}", name, output.trim())));
+        }
+        Ok(())
+    }
+
+    /// Restore the snapshot of the virtual machine with the specified name
+    pub(crate) fn load_snapshot(&mut self, name: &str) -> Result<(), Error> {
+        let output = self.human_monitor_command(&format!("loadvm {}", name))?;
+        if !output.trim().is_empty() {
+            return Err(Error::QmpError(format!("Unable to load snapshot '{}' => {}", name, output.trim())));
+        }
+        Ok(())
+    }
+
+    /// Return the register state of all CPUs
+    pub(crate) fn registers(&mut self) -> Result<String, Error> {
+        self.human_monitor_command("info registers -a")
+    }
+}
+
+/// Commands sent to the running QEMU instance of the workspace over QMP
+#[derive(Subcommand, Clone)]
+pub(crate) enum QmpCommand {
+    /// Send key combinations (e.g. `ctrl-alt-delete`, `ret`) to the virtual machine
+    SendKeys {
+        /// The key combinations, sent one after another. The keys of a combination are QEMU key codes separated by `-`
+        #[arg(required = true)]
+        keys: Vec<String>,
+
+        /// The time in milliseconds the keys are held down
+        #[arg(long)]
+        hold_time: Option<u32>
+    },
+
+    /// Write the content of the display into the file (PNG if the file has the extension `.png`, otherwise PPM)
+    Screendump {
+        file: String
+    },
+
+    /// Save a snapshot of the virtual machine (requires a writable qcow2 drive)
+    Savevm {
+        name: String
+    },
+
+    /// Restore a snapshot of the virtual machine
+    Loadvm {
+        name: String
+    },
+
+    /// Print the register state of all CPUs
+    Registers
+}
+
+/// Connect to the QMP socket of the QEMU instance started by `run-qemu` in the workspace and execute the command
+pub(crate) fn run_qmp_command(args: &Arguments, command: &QmpCommand) -> Result<(), Error> {
+    let socket = qmp_socket_path(&args.workspace_path)?;
+    if !socket.exists() {
+        return Err(Error::FileNotFound(socket.to_str().unwrap().to_owned()));
+    }
+
+    let mut client = QmpClient::connect(&socket, Duration::ZERO)?;
+    match command {
+        QmpCommand::SendKeys { keys, hold_time } => {
+            for combination in keys {
+                info!("Send keys {}", combination.clone().color(Color::Green));
+                client.send_keys(combination, *hold_time)?;
+            }
+        }
+        QmpCommand::Screendump { file } => {
+            let file = path::absolute(Path::new(&args.workspace_path).join(file))?;
+            client.screendump(&file)?;
+            info!("Wrote screendump to {}", file.to_str().unwrap().gradient(Color::Blue));
+        }
+        QmpCommand::Savevm { name } => {
+            client.save_snapshot(name)?;
+            info!("Saved snapshot {}", name.clone().color(Color::Green));
+        }
+        QmpCommand::Loadvm { name } => {
+            client.load_snapshot(name)?;
+            info!("Loaded snapshot {}", name.clone().color(Color::Green));
+        }
+        QmpCommand::Registers => {
+            println!("{}", client.registers()?);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::thread;
+    use std::thread::JoinHandle;
+    use serde_json::{json, Value};
+    use crate::error::Error;
+    use crate::tasks::qemu::qmp::{QmpClient, check_qmp_socket};
+
+    const GREETING: &str = r#"{"QMP": {"version": {"qemu": {"major": 8, "minor": 1}}, "capabilities": []}}"#;
+    const EVENT: &str = r#"{"timestamp": {"seconds": 1, "microseconds": 2}, "event": "RESET", "data": {}}"#;
+
+    /// Start a QMP server stub on the other end of the stream. The stub sends the greeting, then replies to each
+    /// received command with the specified lines and returns the received commands.
+    fn stub(greeting: &'static str, replies: Vec<Vec<String>>) -> (UnixStream, JoinHandle<Vec<Value>>) {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let handle = thread::spawn(move || {
+            let mut reader = BufReader::new(server.try_clone().unwrap());
+            writeln!(server, "{}", greeting).unwrap();
+            let mut commands = Vec::new();
+            for reply in replies {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+                commands.push(serde_json::from_str(&line).unwrap());
+                reply.iter().for_each(|line| writeln!(server, "{}", line).unwrap());
+            }
+            commands
+        });
+        (client, handle)
+    }
+
+    fn reply(value: Value) -> Vec<String> {
+        vec![json!({ "return": value }).to_string()]
+    }
+
+    #[test]
+    fn negotiates_capabilities() {
+        let (stream, stub) = stub(GREETING, vec![reply(json!({}))]);
+        QmpClient::new(stream).unwrap();
+        assert_eq!(stub.join().unwrap(), vec![json!({ "execute": "qmp_capabilities" })]);
+    }
+
+    #[test]
+    fn rejects_invalid_greeting() {
+        let (stream, _stub) = stub(r#"{"return": {}}"#, Vec::new());
+        assert!(matches!(QmpClient::new(stream), Err(Error::QmpError(message)) if message.contains("greeting")));
+    }
+
+    #[test]
+    fn returns_command_reply() {
+        let (stream, stub) = stub(GREETING, vec![
+            reply(json!({})),
+            reply(json!({ "status": "running", "running": true }))
+        ]);
+        let mut client = QmpClient::new(stream).unwrap();
+        let status = client.execute("query-status", None).unwrap();
+        assert_eq!(status, json!({ "status": "running", "running": true }));
+        assert_eq!(stub.join().unwrap()[1], json!({ "execute": "query-status" }));
+    }
+
+    #[test]
+    fn sends_command_arguments() {
+        let (stream, stub) = stub(GREETING, vec![reply(json!({})), reply(json!({})), reply(json!("RAX=0\n"))]);
+        let mut client = QmpClient::new(stream).unwrap();
+        client.send_keys("ctrl-Alt-delete", Some(100)).unwrap();
+        assert_eq!(client.registers().unwrap(), "RAX=0\n");
+
+        let commands = stub.join().unwrap();
+        assert_eq!(commands[1], json!({ "execute": "send-key", "arguments": { "hold-time": 100, "keys": [
+            { "type": "qcode", "data": "ctrl" },
+            { "type": "qcode", "data": "alt" },
+            { "type": "qcode", "data": "delete" }
+        ] } }));
+        assert_eq!(commands[2], json!({ "execute": "human-monitor-command",
+            "arguments": { "command-line": "info registers -a" } }));
+    }
+
+    #[test]
+    fn returns_error_reply() {
+        let (stream, _stub) = stub(GREETING, vec![
+            reply(json!({})),
+            vec![json!({ "error": { "class": "GenericError", "desc": "No display" } }).to_string()]
+        ]);
+        let mut client = QmpClient::new(stream).unwrap();
+        let error = client.screendump("/tmp/screen.png").unwrap_err();
+        assert!(matches!(error, Error::QmpError(message) if message == "Command 'screendump' failed => No display"));
+    }
+
+    #[test]
+    fn skips_interleaved_events() {
+        let (stream, _stub) = stub(GREETING, vec![
+            vec![EVENT.to_owned(), json!({ "return": {} }).to_string()],
+            vec![EVENT.to_owned(), EVENT.to_owned(), json!({ "return": "" }).to_string()],
+            vec![EVENT.to_owned(), json!({ "return": "Error: Device 'disk' is not writable" }).to_string()]
+        ]);
+        let mut client = QmpClient::new(stream).unwrap();
+        client.save_snapshot("first").unwrap();
+        assert!(matches!(client.save_snapshot("second"), Err(Error::QmpError(message))
+            if message == "Unable to save snapshot 'second' => Error: Device 'disk' is not writable"));
+    }
+
+    #[test]
+    fn reports_closed_connection() {
+        let (stream, _stub) = stub(GREETING, vec![reply(json!({})), Vec::new()]);
+        let mut client = QmpClient::new(stream).unwrap();
+        assert!(matches!(client.execute("stop", None), Err(Error::QmpError(message))
+            if message == "Connection closed by QEMU"));
+    }
+
+    #[test]
+    fn detects_running_instance() {
+        let directory = tempfile::tempdir().unwrap();
+        let socket = directory.path().join("qmp.sock");
+        assert!(check_qmp_socket(&socket).is_ok());
+
+        let listener = UnixListener::bind(&socket).unwrap();
+        assert!(matches!(check_qmp_socket(&socket), Err(Error::InstanceRunning(_))));
+
+        // The socket file remains after the listener is closed, like after QEMU was killed
+        drop(listener);
+        assert!(check_qmp_socket(&socket).is_ok());
+        assert!(!socket.exists());
+    }
+}
@@ -0,0 +1,170 @@
+use std::fs::File;
+use std::io::{Read, stdout, Write};
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use colorful::{Color, Colorful};
+use log::{debug, info};
+use regex::Regex;
+use crate::error::Error;
+use crate::symbolizer::Symbolizer;
+
+/// The outcome of a QEMU run with monitored serial output
+#[derive(Clone, Debug)]
+pub(crate) enum SerialOutcome {
+    /// The expected pattern appeared in the serial output
+    Expected,
+    /// The failure pattern appeared in the serial output (with the matching line)
+    FailurePattern(String),
+    /// QEMU exited before any pattern appeared
+    Exited(ExitStatus),
+    /// The timeout elapsed before QEMU exited or any pattern appeared
+    TimedOut
+}
+
+/// Monitor of the serial output of QEMU. The output is forwarded to stdout and optionally written into a log file.
+/// The monitor stops QEMU when the expected or failure pattern appears or the timeout elapses. With symbolizer, the
+/// addresses in the lines forwarded to stdout are rewritten into functions and source lines.
+#[derive(Default)]
+pub(crate) struct SerialMonitor {
+    pub(crate) expect: Option<Regex>,
+    pub(crate) fail_on: Option<Regex>,
+    pub(crate) log_file: Option<PathBuf>,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) symbolizer: Option<Symbolizer>
+}
+
+impl SerialMonitor {
+    /// Whether the serial output needs to be captured by the monitor
+    pub(crate) fn is_active(&self) -> bool {
+        self.expect.is_some() || self.fail_on.is_some() || self.log_file.is_some() || self.timeout.is_some()
+            || self.symbolizer.is_some()
+    }
+
+    /// Run the command with the stdout redirected to the monitor. The command must forward the serial port of the
+    /// virtual machine to stdout (e.g. `-serial stdio` for QEMU). The callback is called with each line of the serial
+    /// output and the stop callback before the monitor stops the virtual machine.
+    pub(crate) fn run<F: FnMut(&str), S: FnOnce(&SerialOutcome)>(&self, command: &mut Command, mut on_line: F,
+                                                                before_stop: S) -> Result<SerialOutcome, Error> {
+        command.stdout(Stdio::piped());
+        let mut log_file = self.log_file.as_ref().map(File::create).transpose()?;
+        if let Some(log_file) = self.log_file.as_ref() {
+            info!("Write serial output to {}", log_file.to_str().unwrap().gradient(Color::Blue));
+        }
+
+        // Forward serial output in a separate thread and send complete lines to the monitor. Lines are forwarded by
+        // the monitor, if they are symbolized.
+        let forward = self.symbolizer.is_none();
+        let mut child = command.spawn()?;
+        let mut serial = child.stdout.take().unwrap();
+        let (sender, receiver) = channel();
+        let reader = thread::spawn(move || -> Result<(), std::io::Error> {
+            let mut buffer = [0_u8; 4096];
+            let mut line = Vec::new();
+            loop {
+                let length = serial.read(&mut buffer)?;
+                if length == 0 {
+                    break;
+                }
+
+                if forward {
+                    stdout().write_all(&buffer[..length])?;
+                    stdout().flush()?;
+                }
+
+                if let Some(log_file) = log_file.as_mut() {
+                    log_file.write_all(&buffer[..length])?;
+                }
+
+                for byte in &buffer[..length] {
+                    if *byte == b'\n' {
+                        let _ = sender.send(String::from_utf8_lossy(&line).trim_end_matches('\r').to_owned());
+                        line.clear();
+                    } else {
+                        line.push(*byte);
+                    }
+                }
+            }
+
+            if !line.is_empty() {
+                let _ = sender.send(String::from_utf8_lossy(&line).into_owned());
+            }
+            Ok(())
+        });
+
+        let start_time = SystemTime::now();
+        let outcome = loop {
+            match receiver.recv_timeout(Duration::from_millis(100)) {
+                Ok(line) => {
+                    if let Some(symbolizer) = self.symbolizer.as_ref() {
+                        println!("{}", symbolizer.symbolize_line(&line));
+                    }
+
+                    on_line(&line);
+                    if self.fail_on.as_ref().is_some_and(|pattern| pattern.is_match(&line)) {
+                        debug!("Failure pattern matched by line '{}'", line);
+                        break SerialOutcome::FailurePattern(line);
+                    }
+
+                    if self.expect.as_ref().is_some_and(|pattern| pattern.is_match(&line)) {
+                        debug!("Expected pattern matched by line '{}'", line);
+                        break SerialOutcome::Expected;
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    // All output is processed, wait for QEMU to exit
+                    if let Some(exit_status) = child.try_wait()? {
+                        break SerialOutcome::Exited(exit_status);
+                    }
+                    thread::sleep(Duration::from_millis(100));
+                }
+            }
+
+            if self.timeout.is_some_and(|timeout| SystemTime::now().duration_since(start_time).unwrap() >= timeout) {
+                break SerialOutcome::TimedOut;
+            }
+        };
+
+        // Stop QEMU, if it's still running. The reader thread ends with the closed pipe, so it's only joined if QEMU
+        // exited by itself.
+        if child.try_wait()?.is_none() {
+            before_stop(&outcome);
+            child.kill()?;
+            child.wait()?;
+            return Ok(outcome);
+        }
+        reader.join().unwrap()?;
+        Ok(outcome)
+    }
+
+    /// Run the command of the emulator with the specified name until it exits or the monitor stops it. If the
+    /// monitor isn't active, the command is only awaited. The outcome is mapped to an error, if the expected pattern
+    /// is missing, the failure pattern matched, the timeout elapsed or the emulator failed.
+    pub(crate) fn supervise<S: FnOnce(&SerialOutcome)>(&self, name: &str, command: &mut Command,
+                                                      before_stop: S) -> Result<(), Error> {
+        if !self.is_active() {
+            let exit_status = command.spawn()?.wait()?;
+            if !exit_status.success() {
+                return Err(Error::ProcessFailed(String::from(name), exit_status.code().unwrap_or(-1)));
+            }
+            return Ok(());
+        }
+
+        match self.run(command, |_| {}, before_stop)? {
+            SerialOutcome::Expected => {
+                info!("Expected pattern appeared in serial output");
+                Ok(())
+            }
+            SerialOutcome::FailurePattern(line) => Err(Error::FailurePatternMatched(line)),
+            SerialOutcome::TimedOut => Err(Error::Timeout(self.timeout.unwrap_or_default().as_secs())),
+            SerialOutcome::Exited(_) if self.expect.is_some() => Err(Error::ExpectedPatternMissing),
+            SerialOutcome::Exited(exit_status) if !exit_status.success() => {
+                Err(Error::ProcessFailed(String::from(name), exit_status.code().unwrap_or(-1)))
+            }
+            SerialOutcome::Exited(_) => Ok(())
+        }
+    }
+}
@@ -0,0 +1,327 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use colorful::{Color, Colorful};
+use log::info;
+use crate::error::Error;
+use crate::tasks::test::{TestOutcome, TestResult};
+
+/// The prefix of the lines of the test protocol in the serial output
+const PROTOCOL_PREFIX: &str = "[test] ";
+
+/// The status of a single kernel test
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) enum TestStatus {
+    Passed,
+    Failed(String),
+    Panicked(String),
+    /// The test was started, but the test binary ended before the test reported a result
+    Unfinished(String)
+}
+
+/// A single kernel test, reported by the test protocol in the serial output
+#[derive(Clone, Debug)]
+pub(crate) struct TestCase {
+    pub(crate) name: String,
+    pub(crate) status: TestStatus,
+    pub(crate) duration: Duration,
+    pub(crate) output: String
+}
+
+/// Parser of the test protocol in the serial output of a test binary. The kernel reports the tests with the following
+/// lines, all other lines are captured as output of the running test:
+///
+/// - `[test] start <name>`
+/// - `[test] pass <name>`
+/// - `[test] fail <name>: <message>`
+/// - `[test] panic <name>: <message>`
+#[derive(Default)]
+pub(crate) struct TestProtocolParser {
+    cases: Vec<TestCase>,
+    running: Option<(String, SystemTime, String)>
+}
+
+impl TestProtocolParser {
+    pub(crate) fn parse_line(&mut self, line: &str) {
+        let Some(message) = line.trim_start().strip_prefix(PROTOCOL_PREFIX) else {
+            if let Some((_, _, output)) = self.running.as_mut() {
+                output.push_str(line);
+                output.push('\n');
+            }
+            return;
+        };
+
+        let (command, argument) = message.split_once(' ').unwrap_or((message, ""));
+        let (name, reason) = argument.split_once(": ").unwrap_or((argument, ""));
+        match command {
+            "start" => {
+                self.finish(TestStatus::Unfinished(String::from("Next test started before result was reported")));
+                self.running = Some((name.trim().to_owned(), SystemTime::now(), String::new()));
+            }
+            "pass" => self.finish_named(name, TestStatus::Passed),
+            "fail" => self.finish_named(name, TestStatus::Failed(reason.to_owned())),
+            "panic" => self.finish_named(name, TestStatus::Panicked(reason.to_owned())),
+            _ => {}
+        }
+    }
+
+    /// Finish the running test or the test with the specified name, if no test is running
+    fn finish_named(&mut self, name: &str, status: TestStatus) {
+        if self.running.is_none() {
+            self.running = Some((name.trim().to_owned(), SystemTime::now(), String::new()));
+        }
+        self.finish(status);
+    }
+
+    fn finish(&mut self, status: TestStatus) {
+        if let Some((name, start_time, output)) = self.running.take() {
+            let duration = SystemTime::now().duration_since(start_time).unwrap_or_default();
+            self.cases.push(TestCase { name, status, duration, output });
+        }
+    }
+
+    /// Return the reported tests. A still running test is reported as unfinished with the specified reason.
+    pub(crate) fn finish_all(mut self, reason: &str) -> Vec<TestCase> {
+        self.finish(TestStatus::Unfinished(reason.to_owned()));
+        self.cases
+    }
+}
+
+fn escape_xml(value: &str) -> String {
+    value.chars().filter(|character| !character.is_control() || matches!(character, '\n' | '\t'))
+        .map(|character| match character {
+            '&' => String::from("&amp;"),
+            '<' => String::from("&lt;"),
+            '>' => String::from("&gt;"),
+            '"' => String::from("&quot;"),
+            '\'' => String::from("&apos;"),
+            character => character.to_string()
+        }).collect()
+}
+
+/// The test cases of a test binary. Binaries without reported tests are represented by a single test case with the
+/// outcome of the binary. The outcome is also added as test case, if the binary failed after all tests passed.
+fn binary_cases(result: &TestResult) -> Vec<TestCase> {
+    let status = match &result.outcome {
+        TestOutcome::Passed => TestStatus::Passed,
+        TestOutcome::Failed(Some(code)) => TestStatus::Failed(format!("QEMU exited with code {}", code)),
+        TestOutcome::Failed(None) => TestStatus::Failed(String::from("QEMU was terminated")),
+        TestOutcome::FailurePattern(line) => TestStatus::Failed(format!("Failure pattern matched by '{}'", line)),
+        TestOutcome::TimedOut => TestStatus::Unfinished(String::from("Timeout elapsed"))
+    };
+
+    let mut cases = result.cases.clone();
+    let all_passed = cases.iter().all(|case| case.status == TestStatus::Passed);
+    if cases.is_empty() || (status != TestStatus::Passed && all_passed) {
+        cases.push(TestCase { name: result.name.clone(), status, duration: result.duration,
+            output: result.output.clone() });
+    }
+    cases
+}
+
+/// Write the results as JUnit XML file. Each test binary is a test suite.
+pub(crate) fn write_junit<P: AsRef<Path>>(path: P, results: &[TestResult]) -> Result<(), Error> {
+    let mut suites = String::new();
+    let (mut total_tests, mut total_failures, mut total_errors) = (0, 0, 0);
+    for result in results {
+        let cases = binary_cases(result);
+        let failures = cases.iter().filter(|case| matches!(case.status, TestStatus::Failed(_))).count();
+        let errors = cases.iter()
+            .filter(|case| matches!(case.status, TestStatus::Panicked(_) | TestStatus::Unfinished(_))).count();
+        total_tests += cases.len();
+        total_failures += failures;
+        total_errors += errors;
+
+        suites.push_str(&format!(
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{:.3}\">\n",
+            escape_xml(&result.name), cases.len(), failures, errors, result.duration.as_secs_f64()));
+        for case in cases {
+            suites.push_str(&format!("    <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\">\n",
+                escape_xml(&case.name), escape_xml(&result.name), case.duration.as_secs_f64()));
+            match &case.status {
+                TestStatus::Passed => {}
+                TestStatus::Failed(message) => suites.push_str(&format!(
+                    "      <failure message=\"{}\"/>\n", escape_xml(message))),
+                TestStatus::Panicked(message) => suites.push_str(&format!(
+                    "      <error type=\"panic\" message=\"{}\"/>\n", escape_xml(message))),
+                TestStatus::Unfinished(message) => suites.push_str(&format!(
+                    "      <error type=\"unfinished\" message=\"{}\"/>\n", escape_xml(message)))
+            }
+            if !case.output.is_empty() {
+                suites.push_str(&format!("      <system-out>{}</system-out>\n", escape_xml(&case.output)));
+            }
+            suites.push_str("    </testcase>\n");
+        }
+        suites.push_str("  </testsuite>\n");
+    }
+
+    let total_time: f64 = results.iter().map(|result| result.duration.as_secs_f64()).sum();
+    let mut file = File::create(&path)?;
+    write!(file, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites tests=\"{}\" failures=\"{}\" \
+        errors=\"{}\" time=\"{:.3}\">\n{}</testsuites>\n", total_tests, total_failures, total_errors, total_time,
+        suites)?;
+    info!("Wrote JUnit report to {}", path.as_ref().to_str().unwrap().gradient(Color::Blue));
+    Ok(())
+}
+
+/// Write the results as TAP (version 13) file. The message, duration and output of the tests are written as YAML
+/// diagnostics.
+pub(crate) fn write_tap<P: AsRef<Path>>(path: P, results: &[TestResult]) -> Result<(), Error> {
+    let cases: Vec<(String, TestCase)> = results.iter()
+        .flat_map(|result| binary_cases(result).into_iter().map(|case| (result.name.clone(), case)))
+        .collect();
+
+    let mut tap = format!("TAP version 13\n1..{}\n", cases.len());
+    for (index, (binary, case)) in cases.iter().enumerate() {
+        let (ok, message) = match &case.status {
+            TestStatus::Passed => ("ok", None),
+            TestStatus::Failed(message) => ("not ok", Some(message)),
+            TestStatus::Panicked(message) => ("not ok", Some(message)),
+            TestStatus::Unfinished(message) => ("not ok", Some(message))
+        };
+        tap.push_str(&format!("{} {} - {}::{}\n", ok, index + 1, binary, case.name));
+        tap.push_str("  ---\n");
+        if let Some(message) = message {
+            tap.push_str(&format!("  message: '{}'\n", message.replace('\'', "''")));
+        }
+        tap.push_str(&format!("  duration_ms: {}\n", case.duration.as_millis()));
+        if !case.output.is_empty() {
+            tap.push_str("  output: |\n");
+            case.output.lines().for_each(|line| tap.push_str(&format!("    {}\n", line)));
+        }
+        tap.push_str("  ...\n");
+    }
+
+    let mut file = File::create(&path)?;
+    file.write_all(tap.as_bytes())?;
+    info!("Wrote TAP report to {}", path.as_ref().to_str().unwrap().gradient(Color::Blue));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::read_to_string;
+    use std::time::Duration;
+    use crate::tasks::report::{escape_xml, TestCase, TestProtocolParser, TestStatus, write_junit, write_tap};
+    use crate::tasks::test::{TestOutcome, TestResult};
+
+    fn case(name: &str, status: TestStatus, output: &str) -> TestCase {
+        TestCase { name: name.to_owned(), status, duration: Duration::from_millis(5), output: output.to_owned() }
+    }
+
+    fn result(name: &str, outcome: TestOutcome, cases: Vec<TestCase>) -> TestResult {
+        TestResult { name: name.to_owned(), outcome, duration: Duration::from_millis(250), cases,
+            output: String::from("serial output\n") }
+    }
+
+    fn parse(lines: &[&str]) -> Vec<TestCase> {
+        let mut parser = TestProtocolParser::default();
+        lines.iter().for_each(|line| parser.parse_line(line));
+        parser.finish_all("Test binary exited")
+    }
+
+    #[test]
+    fn parser_reports_statuses() {
+        let cases = parse(&[
+            "booting",
+            "[test] start memory::alloc",
+            "allocated 16 bytes",
+            "[test] pass memory::alloc",
+            "[test] start memory::free",
+            "[test] fail memory::free: double free",
+            "  [test] start interrupts::breakpoint",
+            "[test] panic interrupts::breakpoint: panicked at 'unexpected trap', src/lib.rs:3:5"
+        ]);
+        assert_eq!(cases.len(), 3);
+        assert_eq!(cases[0].name, "memory::alloc");
+        assert_eq!(cases[0].status, TestStatus::Passed);
+        assert_eq!(cases[0].output, "allocated 16 bytes\n");
+        assert_eq!(cases[1].status, TestStatus::Failed(String::from("double free")));
+        assert_eq!(cases[2].name, "interrupts::breakpoint");
+        assert_eq!(cases[2].status,
+            TestStatus::Panicked(String::from("panicked at 'unexpected trap', src/lib.rs:3:5")));
+    }
+
+    #[test]
+    fn parser_reports_unfinished_tests() {
+        let cases = parse(&["[test] start first", "[test] start second", "hang"]);
+        assert_eq!(cases.len(), 2);
+        assert_eq!(cases[0].status,
+            TestStatus::Unfinished(String::from("Next test started before result was reported")));
+        assert_eq!(cases[1].status, TestStatus::Unfinished(String::from("Test binary exited")));
+        assert_eq!(cases[1].output, "hang\n");
+    }
+
+    #[test]
+    fn parser_accepts_result_without_start() {
+        let cases = parse(&["[test] pass lonely", "[test] unknown command", "[test] fail other: reason: nested"]);
+        assert_eq!(cases.len(), 2);
+        assert_eq!(cases[0].name, "lonely");
+        assert_eq!(cases[0].status, TestStatus::Passed);
+        assert_eq!(cases[1].name, "other");
+        assert_eq!(cases[1].status, TestStatus::Failed(String::from("reason: nested")));
+    }
+
+    #[test]
+    fn escape_xml_escapes_special_and_control_characters() {
+        assert_eq!(escape_xml("<a href=\"x\">'&'</a>"), "&lt;a href=&quot;x&quot;&gt;&apos;&amp;&apos;&lt;/a&gt;");
+        assert_eq!(escape_xml("line\u{1b}[31m\u{0}\n\tnext"), "line[31m\n\tnext");
+    }
+
+    #[test]
+    fn junit_report() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("report.xml");
+        write_junit(&path, &[
+            result("kernel-1", TestOutcome::Passed, vec![
+                case("passes", TestStatus::Passed, ""),
+                case("fails", TestStatus::Failed(String::from("1 < 2")), "assertion\n")
+            ]),
+            result("kernel-2", TestOutcome::TimedOut, Vec::new())
+        ]).unwrap();
+
+        let report = read_to_string(path).unwrap();
+        assert!(report.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+            <testsuites tests=\"3\" failures=\"1\" errors=\"1\" time=\"0.500\">\n"));
+        assert!(report.contains("  <testsuite name=\"kernel-1\" tests=\"2\" failures=\"1\" errors=\"0\" \
+            time=\"0.250\">\n    <testcase name=\"passes\" classname=\"kernel-1\" time=\"0.005\">\n    </testcase>\n"));
+        assert!(report.contains("      <failure message=\"1 &lt; 2\"/>\n      \
+            <system-out>assertion\n</system-out>\n"));
+        assert!(report.contains("<testcase name=\"kernel-2\" classname=\"kernel-2\" time=\"0.250\">\n      \
+            <error type=\"unfinished\" message=\"Timeout elapsed\"/>\n"));
+        assert!(report.ends_with("  </testsuite>\n</testsuites>\n"));
+    }
+
+    #[test]
+    fn junit_report_adds_outcome_of_failed_binary() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("report.xml");
+        write_junit(&path, &[result("kernel", TestOutcome::Failed(Some(3)),
+            vec![case("passes", TestStatus::Passed, "")])]).unwrap();
+
+        let report = read_to_string(path).unwrap();
+        assert!(report.contains("tests=\"2\" failures=\"1\" errors=\"0\""));
+        assert!(report.contains("<failure message=\"QEMU exited with code 3\"/>"));
+    }
+
+    #[test]
+    fn tap_report() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("report.tap");
+        write_tap(&path, &[
+            result("kernel", TestOutcome::Passed, vec![
+                case("passes", TestStatus::Passed, ""),
+                case("panics", TestStatus::Panicked(String::from("it's broken")), "line 1\nline 2\n")
+            ]),
+            result("other", TestOutcome::FailurePattern(String::from("PANIC")), Vec::new())
+        ]).unwrap();
+
+        assert_eq!(read_to_string(path).unwrap(), "TAP version 13\n1..3\n\
+            ok 1 - kernel::passes\n  ---\n  duration_ms: 5\n  ...\n\
+            not ok 2 - kernel::panics\n  ---\n  message: 'it''s broken'\n  duration_ms: 5\n  output: |\n    \
+            line 1\n    line 2\n  ...\n\
+            not ok 3 - other::other\n  ---\n  message: 'Failure pattern matched by ''PANIC'''\n  \
+            duration_ms: 250\n  output: |\n    serial output\n  ...\n");
+    }
+}
@@ -0,0 +1,48 @@
+use std::process::Command;
+use crate::ImageFormat;
+use crate::arch::Architecture;
+use crate::error::Error;
+use crate::tasks::runner::{RunContext, Runner};
+use crate::utils::find_in_path;
+
+/// Runner of the kernel binary in cloud-hypervisor. The kernel is booted directly (PVH on x86-64) without firmware, the
+/// artifact is attached as disk.
+pub(crate) struct CloudHypervisorRunner;
+
+impl Runner for CloudHypervisorRunner {
+    fn name(&self) -> &'static str {
+        "cloud-hypervisor"
+    }
+
+    fn command(&self, context: &RunContext) -> Result<Command, Error> {
+        let args = context.args;
+        if !matches!(args.target_arch, Architecture::X86_64 | Architecture::ARM64) {
+            return Err(Error::UnsupportedRunner(String::from(self.name()), args.target_arch.to_string()));
+        }
+
+        let hypervisor_path = find_in_path("cloud-hypervisor")
+            .ok_or(Error::ExecutableNotFound(String::from("cloud-hypervisor")))?;
+        let kernel = context.kernel.as_ref().ok_or(Error::FileNotFound(String::from("kernel binary")))?;
+
+        let mut command = Command::new(hypervisor_path);
+        command
+            .arg("--kernel").arg(kernel)
+            .arg("--cpus").arg(format!("boot={}", context.smp()))
+            .arg("--memory").arg(format!("size={}M", context.memory_megabytes()?))
+            .arg("--serial").arg("tty")
+            .arg("--console").arg("off");
+
+        match context.artifact.format {
+            ImageFormat::Iso => {
+                command.arg("--disk").arg(format!("path={},readonly=on",
+                    context.workspace_file(&context.artifact.iso_file)?.to_str().unwrap()));
+            }
+            ImageFormat::Raw => {
+                command.arg("--disk").arg(format!("path={}",
+                    context.workspace_file(&context.artifact.image_file)?.to_str().unwrap()));
+            }
+            ImageFormat::Directory => {}
+        }
+        Ok(command)
+    }
+}
@@ -0,0 +1,45 @@
+use std::path;
+use std::process::Command;
+use log::debug;
+use crate::error::Error;
+use crate::tasks::runner::{RunContext, Runner};
+
+/// Runner of a custom command template. The template is split at whitespaces and the placeholders `{iso}`, `{image}`,
+/// `{directory}`, `{kernel}`, `{memory}` (in megabytes), `{smp}`, `{arch}` and `{workspace}` are replaced in each
+/// argument.
+pub(crate) struct CustomRunner;
+
+impl Runner for CustomRunner {
+    fn name(&self) -> &'static str {
+        "custom runner"
+    }
+
+    fn command(&self, context: &RunContext) -> Result<Command, Error> {
+        let template = context.qemu_args.runner_command.as_ref().or(context.config.runner.command.as_ref())
+            .ok_or(Error::InvalidParameter(String::from("runner_command")))?;
+        let artifact = context.artifact;
+        let placeholders = [
+            ("{iso}", context.workspace_file(&artifact.iso_file)?.to_str().unwrap().to_owned()),
+            ("{image}", context.workspace_file(&artifact.image_file)?.to_str().unwrap().to_owned()),
+            ("{directory}", context.workspace_file(&artifact.esp_directory)?.to_str().unwrap().to_owned()),
+            ("{kernel}", context.kernel.as_ref().map(|kernel| kernel.to_str().unwrap().to_owned()).unwrap_or_default()),
+            ("{memory}", context.memory_megabytes()?.to_string()),
+            ("{smp}", context.smp().to_string()),
+            ("{arch}", context.args.target_arch.to_string()),
+            ("{workspace}", path::absolute(&context.args.workspace_path)?.to_str().unwrap().to_owned())
+        ];
+
+        let mut arguments = template.split_whitespace().map(|argument| {
+            placeholders.iter().fold(argument.to_owned(), |argument, (placeholder, value)| {
+                argument.replace(placeholder, value)
+            })
+        });
+        let program = arguments.next().ok_or(Error::InvalidParameter(String::from("runner_command")))?;
+        debug!("Run custom command {}", program);
+
+        let mut command = Command::new(program);
+        command.args(arguments);
+        command.current_dir(&context.args.workspace_path);
+        Ok(command)
+    }
+}
@@ -0,0 +1,135 @@
+use std::path;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use clap::ValueEnum;
+use colorful::{Color, Colorful};
+use log::info;
+use serde::Deserialize;
+use crate::{Arguments, ArtifactArguments, QemuArguments};
+use crate::config::Config;
+use crate::error::Error;
+use crate::project::{CargoProject, ProjectKind};
+use crate::tasks::debugger::artifact_path;
+use crate::tasks::qemu::{memory_megabytes, run_qemu, serial_monitor};
+use crate::tasks::runner::cloud_hypervisor::CloudHypervisorRunner;
+use crate::tasks::runner::custom::CustomRunner;
+
+pub(crate) mod cloud_hypervisor;
+pub(crate) mod custom;
+
+/// The emulator or hypervisor the artifact is run with
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum RunnerKind {
+    /// QEMU with firmware, debugger, QMP and exception log support
+    Qemu,
+    /// cloud-hypervisor with direct boot of the kernel binary (x86-64 and ARM64 only)
+    CloudHypervisor,
+    /// Custom command template with placeholders for the artifacts
+    Custom
+}
+
+impl RunnerKind {
+    /// Return the backend of the runner. QEMU has no backend, because it's run by `run_qemu`.
+    pub(crate) fn backend(&self) -> Option<Box<dyn Runner>> {
+        match self {
+            RunnerKind::Qemu => None,
+            RunnerKind::CloudHypervisor => Some(Box::new(CloudHypervisorRunner)),
+            RunnerKind::Custom => Some(Box::new(CustomRunner))
+        }
+    }
+}
+
+/// The arguments and artifacts the runner creates the command with
+pub(crate) struct RunContext<'a> {
+    pub(crate) args: &'a Arguments,
+    pub(crate) config: &'a Config,
+    pub(crate) artifact: &'a ArtifactArguments,
+    pub(crate) qemu_args: &'a QemuArguments,
+    pub(crate) kernel: Option<PathBuf>
+}
+
+impl<'a> RunContext<'a> {
+    /// The absolute path of the file or directory relative to the workspace. The paths are passed as absolute paths,
+    /// because the runners may run in the workspace.
+    pub(crate) fn workspace_file(&self, file: &str) -> Result<PathBuf, Error> {
+        Ok(path::absolute(Path::new(&self.args.workspace_path).join(file))?)
+    }
+
+    /// The memory size of the virtual machine in megabytes, merged from command line and config (default: 512)
+    pub(crate) fn memory_megabytes(&self) -> Result<u64, Error> {
+        memory_megabytes(self.qemu_args, self.config)
+    }
+
+    /// The count of CPUs of the virtual machine, merged from command line and config (default: 1)
+    pub(crate) fn smp(&self) -> u32 {
+        self.qemu_args.smp.or(self.config.qemu.smp).unwrap_or(1)
+    }
+}
+
+/// Backend running the artifact in an emulator or hypervisor other than QEMU
+pub(crate) trait Runner {
+    /// The name of the emulator, used in log and error messages
+    fn name(&self) -> &'static str;
+
+    /// Create the command running the artifact. The command must forward the serial port of the virtual machine to
+    /// stdout, so the serial output can be monitored.
+    fn command(&self, context: &RunContext) -> Result<Command, Error>;
+}
+
+/// Return the backend of the runner selected by the command line or config (default: QEMU). The options only
+/// supported by QEMU (firmware, machine, devices, debugging, QMP and direct boot) are rejected for the other runners.
+pub(crate) fn runner_backend(config: &Config, qemu_args: &QemuArguments) -> Result<Option<Box<dyn Runner>>, Error> {
+    let runner = qemu_args.runner.or(config.runner.backend).unwrap_or(RunnerKind::Qemu);
+    let Some(backend) = runner.backend() else {
+        return Ok(None);
+    };
+
+    let qemu_options = [
+        (qemu_args.direct, "--direct"),
+        (qemu_args.storage.is_some(), "--storage"),
+        (qemu_args.net.is_some(), "--net"),
+        (qemu_args.nic_model.is_some(), "--nic-model"),
+        (qemu_args.tap_interface.is_some(), "--tap-interface"),
+        (!qemu_args.disk.is_empty(), "--disk"),
+        (qemu_args.usb, "--usb"),
+        (!qemu_args.device.is_empty(), "--device"),
+        (qemu_args.debugging, "--debugging"),
+        (qemu_args.attach_debugger, "--attach-debugger"),
+        (qemu_args.bootloader_load_address.is_some(), "--bootloader-load-address"),
+        (qemu_args.exception_info, "--exception-info"),
+        (qemu_args.machine.is_some(), "--machine"),
+        (qemu_args.cpu.is_some(), "--cpu"),
+        (qemu_args.kvm, "--kvm"),
+        (qemu_args.no_kvm, "--no-kvm"),
+        (qemu_args.firmware.is_some(), "--firmware"),
+        (qemu_args.firmware_path.is_some(), "--firmware-path"),
+        (qemu_args.reset_nvram, "--reset-nvram"),
+        (qemu_args.screendump_on_exit.is_some(), "--screendump-on-exit"),
+        (qemu_args.savevm.is_some(), "--savevm"),
+        (qemu_args.loadvm.is_some(), "--loadvm"),
+        (qemu_args.secure_boot_vars.is_some(), "--secure-boot-vars")
+    ];
+    if let Some((_, option)) = qemu_options.iter().find(|(enabled, _)| *enabled) {
+        return Err(Error::UnsupportedRunner(String::from(backend.name()), String::from(*option)));
+    }
+    Ok(Some(backend))
+}
+
+/// Run the artifact with the runner selected by the command line or config (default: QEMU). The serial output of all
+/// runners is monitored with the patterns, log file and timeout of the arguments.
+pub(crate) fn run_image(args: &Arguments, config: &Config, projects: &[CargoProject], artifact: &ArtifactArguments,
+                        qemu_args: &QemuArguments, kernel: Option<&Path>) -> Result<(), Error> {
+    let Some(backend) = runner_backend(config, qemu_args)? else {
+        return run_qemu(args, config, projects, artifact, qemu_args, kernel);
+    };
+
+    let kernel = kernel.map(Path::to_path_buf).or_else(|| artifact_path(args, projects, ProjectKind::Kernel))
+        .filter(|kernel| kernel.exists()).map(path::absolute).transpose()?;
+    let context = RunContext { args, config, artifact, qemu_args, kernel };
+    let mut command = backend.command(&context)?;
+    info!("Run image with {}", backend.name().color(Color::Green));
+
+    let monitor = serial_monitor(args, qemu_args, context.kernel.as_deref())?;
+    monitor.supervise(backend.name(), &mut command, |_| {})
+}
@@ -0,0 +1,114 @@
+use std::fs::create_dir_all;
+use std::path;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use colorful::{Color, Colorful};
+use log::{debug, info};
+use crate::Arguments;
+use crate::config::Config;
+use crate::error::Error;
+use crate::tasks::qemu::firmware::{Firmware, FirmwareFiles};
+use crate::utils::{find_in_path, state_directory};
+
+/// GUID used as the owner of all generated signature list entries
+const OWNER_GUID: &str = "4f53494d-4147-4500-0000-000000000001";
+
+/// Sign the specified EFI binary (bootloader or unified kernel image) with the specified key and certificate with
+/// `sbsign` and return the path of the signed binary. The signed binary is stored in the state directory of the
+/// workspace, so the build artifact of Cargo stays untouched.
+pub(crate) fn sign_efi_binary<P: AsRef<Path>>(args: &Arguments, key: &str, certificate: &str,
+                                              binary: P) -> Result<PathBuf, Error> {
+    let sbsign_path = find_in_path("sbsign").ok_or(Error::ExecutableNotFound(String::from("sbsign")))?;
+    let binary = path::absolute(binary.as_ref())?;
+
+    // The binary and the output are passed as absolute paths, because they're already joined with the workspace. The
+    // key and the certificate are relative to the workspace, in which sbsign runs.
+    let signed_directory = path::absolute(state_directory(&args.workspace_path)?.join("signed"))?;
+    if !signed_directory.exists() {
+        debug!("Directory {} not found! Creating it...", signed_directory.to_str().unwrap());
+        create_dir_all(&signed_directory)?;
+    }
+
+    let signed_binary = signed_directory.join(binary.file_name()
+        .ok_or(Error::InvalidParameter(binary.to_string_lossy().into_owned()))?);
+    info!("Sign {} with certificate {}", binary.to_str().unwrap().gradient(Color::Cyan),
+        certificate.gradient(Color::Green));
+
+    let mut command = Command::new(sbsign_path);
+    command
+        .arg("--key").arg(key)
+        .arg("--cert").arg(certificate)
+        .arg("--output").arg(&signed_binary)
+        .arg(&binary);
+    command.current_dir(&args.workspace_path);
+
+    let exit_status = command.status()?;
+    if !exit_status.success() {
+        return Err(Error::ProcessFailed(String::from("sbsign"), exit_status.code().unwrap()));
+    }
+    Ok(signed_binary)
+}
+
+/// Generate a self-signed test PK, KEK and db set into the output directory and enroll them into a copy of the
+/// specified OVMF variable store template. The enrolled store is written as `OVMF_VARS.fd` into the output directory
+/// and can be passed to `run-qemu --secure-boot-vars`.
+pub(crate) fn generate_keys(args: &Arguments, config: &Config, output_directory: &String,
+                            vars_template: &Option<String>) -> Result<(), Error> {
+    let openssl_path = find_in_path("openssl").ok_or(Error::ExecutableNotFound(String::from("openssl")))?;
+    let output_directory = Path::new(&args.workspace_path).join(output_directory);
+    if !output_directory.exists() {
+        debug!("Directory {} not found! Creating it...", output_directory.to_str().unwrap());
+        create_dir_all(&output_directory)?;
+    }
+
+    // Generate keys and certificates
+    for (name, common_name) in [("PK", "Platform Key"), ("KEK", "Key Exchange Key"), ("db", "Signature Database")] {
+        info!("Generate {} ({}) in {}", name.color(Color::Green), common_name,
+            output_directory.to_str().unwrap().gradient(Color::Blue));
+        let mut command = Command::new(&openssl_path);
+        command
+            .arg("req").arg("-new").arg("-x509")
+            .arg("-newkey").arg("rsa:2048").arg("-nodes")
+            .arg("-sha256").arg("-days").arg("3650")
+            .arg("-subj").arg(format!("/CN=OSImage Test {}/", common_name))
+            .arg("-keyout").arg(format!("{}.key", name))
+            .arg("-out").arg(format!("{}.crt", name));
+        command.current_dir(&output_directory);
+
+        let exit_status = command.output()?.status;
+        if !exit_status.success() {
+            return Err(Error::ProcessFailed(String::from("openssl"), exit_status.code().unwrap()));
+        }
+    }
+
+    // Enroll keys into the variable store
+    let vars_template = match vars_template {
+        Some(vars_template) => Path::new(&args.workspace_path).join(vars_template),
+        None => match Firmware::Ovmf.locate(args.target_arch, &args.workspace_path,
+                                            config.qemu.firmware_path.as_ref(), true)? {
+            FirmwareFiles::Split { vars_template, .. } => vars_template,
+            _ => return Err(Error::FirmwareNotFound(Firmware::Ovmf.to_string()))
+        }
+    };
+    if !vars_template.exists() {
+        return Err(Error::FileNotFound(vars_template.to_string_lossy().into_owned()));
+    }
+
+    info!("Enroll generated keys into {}", vars_template.to_str().unwrap().gradient(Color::Cyan));
+    let virt_fw_vars_path = find_in_path("virt-fw-vars")
+        .ok_or(Error::ExecutableNotFound(String::from("virt-fw-vars")))?;
+    let mut command = Command::new(virt_fw_vars_path);
+    command
+        .arg("--input").arg(&vars_template)
+        .arg("--output").arg(output_directory.join("OVMF_VARS.fd"))
+        .arg("--set-pk").arg(OWNER_GUID).arg(output_directory.join("PK.crt"))
+        .arg("--add-kek").arg(OWNER_GUID).arg(output_directory.join("KEK.crt"))
+        .arg("--add-db").arg(OWNER_GUID).arg(output_directory.join("db.crt"))
+        .arg("--secure-boot");
+
+    let exit_status = command.status()?;
+    if !exit_status.success() {
+        return Err(Error::ProcessFailed(String::from("virt-fw-vars"), exit_status.code().unwrap()));
+    }
+    Ok(())
+}
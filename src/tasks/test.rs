@@ -0,0 +1,213 @@
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+use std::time::{Duration, SystemTime};
+use colorful::{Color, Colorful};
+use log::{debug, info};
+use serde_json::Value;
+use crate::{Arguments, ArtifactArguments, BuildArguments, QemuArguments, TestArguments};
+use crate::arch::Architecture;
+use crate::config::Config;
+use crate::error::Error;
+use crate::project::{CargoProject, ProjectKind};
+use crate::tasks::build::{build_image, cargo_command};
+use crate::tasks::qemu::{qemu_command, serial_monitor};
+use crate::tasks::qemu::serial::SerialOutcome;
+use crate::tasks::report::{TestCase, TestProtocolParser, TestStatus, write_junit, write_tap};
+use crate::utils::find_in_path;
+
+/// The default time a test binary is allowed to run until it's killed
+const DEFAULT_TIMEOUT: u64 = 300;
+
+/// The default exit code of QEMU for passed tests with `isa-debug-exit` (`(0x10 << 1) | 1`)
+const DEFAULT_SUCCESS_EXIT_CODE: i32 = 33;
+
+/// The outcome of a test binary
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) enum TestOutcome {
+    Passed,
+    Failed(Option<i32>),
+    FailurePattern(String),
+    TimedOut
+}
+
+/// The result of a test binary run in QEMU
+#[derive(Clone, Debug)]
+pub(crate) struct TestResult {
+    pub(crate) name: String,
+    pub(crate) outcome: TestOutcome,
+    pub(crate) duration: Duration,
+    /// The tests reported by the test protocol in the serial output
+    pub(crate) cases: Vec<TestCase>,
+    /// The complete serial output of the test binary
+    pub(crate) output: String
+}
+
+/// Build the test harness of the kernel project with `cargo test --no-run` and return the paths of the test binaries
+fn build_test_binaries(args: &Arguments, project: &CargoProject) -> Result<Vec<PathBuf>, Error> {
+    let cargo_path = find_in_path("cargo").ok_or(Error::ExecutableNotFound(String::from("cargo")))?;
+    let project_name = project.manifest.package().name();
+    info!("Build test harness of {} with `{}`", project_name.color(Color::Green), "cargo test".color(Color::Red));
+
+    let mut command = cargo_command(args, &cargo_path, project, "test");
+    command.arg("--no-run").arg("--message-format=json-render-diagnostics");
+    command.stdout(Stdio::piped()).stderr(Stdio::inherit());
+    let output = command.output()?;
+    if !output.status.success() {
+        return Err(Error::BuildFailed(String::from(project_name), output.status.code().unwrap_or(-1)));
+    }
+
+    // Collect executables of test artifacts from the JSON messages of Cargo
+    let mut binaries = Vec::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let Ok(message) = serde_json::from_str::<Value>(line) else {
+            continue;
+        };
+
+        if message["reason"] == "compiler-artifact" && message["profile"]["test"] == true {
+            if let Some(executable) = message["executable"].as_str() {
+                debug!(" - Test binary: {}", executable.gradient(Color::Cyan));
+                binaries.push(PathBuf::from(executable));
+            }
+        }
+    }
+    Ok(binaries)
+}
+
+/// Check whether the exit status of QEMU reports passed tests. On x86 the kernel reports the result with the
+/// `isa-debug-exit` device, on ARM and RISC-V with the semihosting exit call.
+fn is_success(architecture: Architecture, exit_status: ExitStatus, success_exit_code: i32) -> bool {
+    match architecture {
+        Architecture::X86_64 | Architecture::X86 => exit_status.code() == Some(success_exit_code),
+        _ => exit_status.success()
+    }
+}
+
+/// Runner of the test binaries with the arguments of the test command
+struct TestRunner<'a> {
+    args: &'a Arguments,
+    config: &'a Config,
+    projects: &'a [CargoProject],
+    artifact: &'a ArtifactArguments,
+    build_args: &'a BuildArguments,
+    qemu_args: &'a QemuArguments,
+    timeout: Duration,
+    success_exit_code: i32
+}
+
+impl<'a> TestRunner<'a> {
+    /// Run the specified test binary headless in QEMU (booted directly or in the image) and enforce the timeout
+    fn run_binary(&self, binary: &Path) -> Result<TestResult, Error> {
+        let name = binary.file_name().unwrap().to_string_lossy().into_owned();
+        info!("Run test binary {}", name.clone().gradient(Color::Cyan));
+        if !self.qemu_args.direct {
+            build_image(self.args, self.config, self.projects.to_vec(), self.artifact, self.build_args, Some(binary))?;
+        }
+
+        let mut qemu_args = self.qemu_args.clone();
+        qemu_args.headless = true;
+        let mut command = qemu_command(self.args, self.config, self.artifact, &qemu_args, Some(binary))?;
+        match self.args.target_arch {
+            Architecture::X86_64 | Architecture::X86 => {
+                command.arg("-device").arg("isa-debug-exit,iobase=0xf4,iosize=0x04");
+            }
+            _ => {
+                command.arg("-semihosting-config").arg("enable=on,target=native");
+            }
+        }
+
+        // Run QEMU with monitored serial output and map the outcome to the test outcome
+        let mut monitor = serial_monitor(self.args, &qemu_args, Some(binary))?;
+        monitor.timeout = Some(self.timeout);
+        let start_time = SystemTime::now();
+        let mut parser = TestProtocolParser::default();
+        let mut output = String::new();
+        let outcome = match monitor.run(&mut command, |line| {
+            parser.parse_line(line);
+            output.push_str(line);
+            output.push('\n');
+        }, |_| {})? {
+            SerialOutcome::Expected => TestOutcome::Passed,
+            SerialOutcome::FailurePattern(line) => TestOutcome::FailurePattern(line),
+            SerialOutcome::TimedOut => TestOutcome::TimedOut,
+            SerialOutcome::Exited(exit_status) if is_success(self.args.target_arch, exit_status,
+                                                             self.success_exit_code) => TestOutcome::Passed,
+            SerialOutcome::Exited(exit_status) => TestOutcome::Failed(exit_status.code())
+        };
+
+        // A test still running at the end of the binary is reported as unfinished
+        let reason = match &outcome {
+            TestOutcome::Passed => String::from("Test binary exited before the test reported a result"),
+            TestOutcome::Failed(Some(code)) => format!("QEMU exited with code {}", code),
+            TestOutcome::Failed(None) => String::from("QEMU was terminated"),
+            TestOutcome::FailurePattern(line) => format!("Failure pattern matched by '{}'", line),
+            TestOutcome::TimedOut => format!("Timeout elapsed after {}s", self.timeout.as_secs())
+        };
+        let cases = parser.finish_all(&reason);
+        Ok(TestResult { name, outcome, duration: SystemTime::now().duration_since(start_time).unwrap(), cases, output })
+    }
+}
+
+/// Build the test binaries of all kernel projects, run each of them in QEMU and print the aggregated results
+pub(crate) fn run_tests(args: &Arguments, config: &Config, projects: &[CargoProject], artifact: &ArtifactArguments,
+                        build_args: &BuildArguments, qemu_args: &QemuArguments,
+                        test_args: &TestArguments) -> Result<(), Error> {
+    let timeout = Duration::from_secs(qemu_args.timeout.or(config.test.timeout).unwrap_or(DEFAULT_TIMEOUT));
+    let runner = TestRunner {
+        args,
+        config,
+        projects,
+        artifact,
+        build_args,
+        qemu_args,
+        timeout,
+        success_exit_code: test_args.success_exit_code.or(config.test.success_exit_code)
+            .unwrap_or(DEFAULT_SUCCESS_EXIT_CODE)
+    };
+
+    let mut results = Vec::new();
+    for project in projects.iter().filter(|project| project.kind == ProjectKind::Kernel) {
+        for binary in build_test_binaries(args, project)? {
+            results.push(runner.run_binary(&binary)?);
+        }
+    }
+
+    // Print results
+    info!("Test results:");
+    for result in &results {
+        let outcome = match &result.outcome {
+            TestOutcome::Passed => "passed".color(Color::Green),
+            TestOutcome::Failed(Some(code)) => format!("failed (exit code {})", code).color(Color::Red),
+            TestOutcome::Failed(None) => "failed (terminated)".color(Color::Red),
+            TestOutcome::FailurePattern(line) => format!("failed ('{}')", line).color(Color::Red),
+            TestOutcome::TimedOut => format!("timed out after {}s", timeout.as_secs()).color(Color::Red)
+        };
+        info!(" - {} {} in {}ms", result.name.clone().gradient(Color::Cyan), outcome, result.duration.as_millis());
+        for case in &result.cases {
+            let status = match &case.status {
+                TestStatus::Passed => "passed".color(Color::Green),
+                TestStatus::Failed(message) => format!("failed ({})", message).color(Color::Red),
+                TestStatus::Panicked(message) => format!("panicked ({})", message).color(Color::Red),
+                TestStatus::Unfinished(message) => format!("unfinished ({})", message).color(Color::Red)
+            };
+            info!("   - {} {} in {}ms", case.name, status, case.duration.as_millis());
+        }
+    }
+
+    // Write reports
+    if let Some(junit) = test_args.junit.as_ref().or(config.test.junit.as_ref()) {
+        write_junit(Path::new(&args.workspace_path).join(junit), &results)?;
+    }
+
+    if let Some(tap) = test_args.tap.as_ref().or(config.test.tap.as_ref()) {
+        write_tap(Path::new(&args.workspace_path).join(tap), &results)?;
+    }
+
+    // A test binary also fails, if one of its reported tests failed
+    let failed = results.iter().filter(|result| result.outcome != TestOutcome::Passed
+        || result.cases.iter().any(|case| case.status != TestStatus::Passed)).count();
+    if failed > 0 {
+        return Err(Error::TestsFailed(failed, results.len()));
+    }
+    info!("All {} test binaries passed", results.len());
+    Ok(())
+}
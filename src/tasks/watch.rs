@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::thread::ScopedJoinHandle;
+use std::time::{Duration, SystemTime};
+use colorful::{Color, Colorful};
+use log::{debug, error, info, warn};
+use crate::{Arguments, ArtifactArguments, BuildArguments, QemuArguments, WatchArguments};
+use crate::config::Config;
+use crate::error::Error;
+use crate::project::{CargoProject, ProjectKind};
+use crate::tasks::build::{build_image, build_projects};
+use crate::tasks::qemu::qmp::{QmpClient, qmp_socket_path};
+use crate::tasks::runner::{run_image, runner_backend};
+
+/// The interval in which the modification times of the source files are polled
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The modification times of the source files of all projects
+type Snapshot = HashMap<PathBuf, SystemTime>;
+
+/// Collect the modification times of all files in the directory recursively
+fn collect_files(path: &Path, snapshot: &mut Snapshot) {
+    let Ok(metadata) = fs::metadata(path) else {
+        return;
+    };
+
+    if metadata.is_dir() {
+        if let Ok(entries) = fs::read_dir(path) {
+            entries.flatten().for_each(|entry| collect_files(&entry.path(), snapshot));
+        }
+    } else if let Ok(modified) = metadata.modified() {
+        snapshot.insert(path.to_path_buf(), modified);
+    }
+}
+
+/// Take a snapshot of the source files of the projects. The sources are the manifest, the build script and the
+/// `src` directory of each project.
+fn take_snapshot(projects: &[CargoProject]) -> Snapshot {
+    let mut snapshot = Snapshot::new();
+    for project in projects {
+        let path = Path::new(&project.path);
+        for source in [path.join("Cargo.toml"), path.join("build.rs"), path.join("src")] {
+            collect_files(&source, &mut snapshot);
+        }
+    }
+    snapshot
+}
+
+/// Return the files, which were created, modified or removed between the snapshots
+fn changed_files(old: &Snapshot, new: &Snapshot) -> Vec<PathBuf> {
+    let mut changed: Vec<PathBuf> = new.iter()
+        .filter(|(path, modified)| old.get(*path) != Some(modified))
+        .map(|(path, _)| path.clone())
+        .collect();
+    changed.extend(old.keys().filter(|path| !new.contains_key(*path)).cloned());
+    changed
+}
+
+/// Return the names of the projects containing the changed files. Files are assigned to the innermost project, so
+/// the projects of nested workspace members aren't affected by the changes of the other members.
+fn affected_projects(projects: &[CargoProject], changed: &[PathBuf]) -> Vec<String> {
+    let mut affected = Vec::new();
+    for file in changed {
+        let project = projects.iter()
+            .filter(|project| file.starts_with(&project.path))
+            .max_by_key(|project| project.path.len());
+        if let Some(project) = project {
+            let name = project.manifest.package().name().to_owned();
+            if !affected.contains(&name) {
+                affected.push(name);
+            }
+        }
+    }
+    affected
+}
+
+/// Block until source files of the projects changed and no further changes happened within the debounce time.
+/// Return the names of the affected projects.
+fn wait_for_changes(projects: &[CargoProject], snapshot: &mut Snapshot, debounce: Duration) -> Vec<String> {
+    let mut changed = Vec::new();
+    let mut last_change = None;
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        let new_snapshot = take_snapshot(projects);
+        let files = changed_files(snapshot, &new_snapshot);
+        *snapshot = new_snapshot;
+
+        if !files.is_empty() {
+            files.iter().for_each(|file| debug!("Changed source file {}", file.to_str().unwrap()));
+            changed.extend(files);
+            last_change = Some(SystemTime::now());
+        } else if last_change.is_some_and(|time| time.elapsed().unwrap_or_default() >= debounce) {
+            let affected = affected_projects(projects, &changed);
+            if !affected.is_empty() {
+                return affected;
+            }
+            changed.clear();
+            last_change = None;
+        }
+    }
+}
+
+/// Watch the source directories of the projects and call the rebuild function after each change. The rebuild builds
+/// all projects, so the dependents of a changed workspace library are rebuilt too (Cargo skips the unchanged projects).
+/// Errors of the rebuild are logged, so the watch mode continues until it's interrupted.
+fn watch_projects<F: FnMut() -> Result<(), Error>>(projects: &[CargoProject], watch: &WatchArguments,
+                                                  mut rebuild: F) -> ! {
+    let mut snapshot = take_snapshot(projects);
+    info!("Watching {} source files of {} projects for changes", snapshot.len(), projects.len());
+    loop {
+        let affected = wait_for_changes(projects, &mut snapshot, Duration::from_millis(watch.debounce));
+        info!("Detected changes in {}, rebuilding...", affected.join(", ").color(Color::Green));
+        match rebuild() {
+            Ok(()) => info!("Rebuilt successfully, waiting for changes"),
+            Err(error) => error!("Unable to rebuild => {}", error)
+        }
+    }
+}
+
+/// Build the image once and rebuild the projects and the image on each change of the sources
+pub(crate) fn watch_image(args: &Arguments, config: &Config, projects: Vec<CargoProject>, artifact: &ArtifactArguments,
+                          build_args: &BuildArguments, watch: &WatchArguments) -> Result<(), Error> {
+    build_image(args, config, projects.clone(), artifact, build_args, None)?;
+    watch_projects(&projects, watch, || build_image(args, config, projects.clone(), artifact, build_args, None))
+}
+
+/// Quit the QEMU instance of the workspace over QMP and wait until it exited
+fn stop_instance(args: &Arguments, instance: ScopedJoinHandle<()>) -> Result<(), Error> {
+    if !instance.is_finished() {
+        info!("Stop running QEMU instance");
+        let mut client = QmpClient::connect(qmp_socket_path(&args.workspace_path)?, Duration::from_secs(5))?;
+        client.execute("quit", None)?;
+    }
+
+    if instance.join().is_err() {
+        warn!("QEMU instance panicked");
+    }
+    Ok(())
+}
+
+/// Build and run the image, then rebuild the projects and the image on each change of the sources. If
+/// restart is enabled, the QEMU instance is stopped before the rebuild and started again with the updated image.
+/// Otherwise the instance keeps running and the updated image is used after the instance was restarted manually.
+pub(crate) fn watch_and_run(args: &Arguments, config: &Config, projects: Vec<CargoProject>,
+                            artifact: &ArtifactArguments, build_args: &BuildArguments, qemu_args: &QemuArguments,
+                            watch: &WatchArguments) -> Result<(), Error> {
+    // The instance is stopped over QMP, so only QEMU can be restarted
+    if let Some(backend) = runner_backend(config, qemu_args)?.filter(|_| watch.restart) {
+        return Err(Error::UnsupportedRunner(String::from(backend.name()), String::from("restarts in watch mode")));
+    }
+
+    // The image isn't needed, if the kernel is booted directly
+    let build = || {
+        if qemu_args.direct {
+            build_projects(args, &projects, ProjectKind::Kernel)
+        } else {
+            build_image(args, config, projects.clone(), artifact, build_args, None)
+        }
+    };
+
+    build()?;
+    thread::scope(|scope| {
+        let start = || scope.spawn(|| {
+            if let Err(error) = run_image(args, config, &projects, artifact, qemu_args, None) {
+                error!("Unable to run image => {}", error);
+            }
+        });
+        let mut instance = Some(start());
+        watch_projects(&projects, watch, || {
+            if watch.restart {
+                if let Some(instance) = instance.take() {
+                    stop_instance(args, instance)?;
+                }
+            }
+
+            build()?;
+            if watch.restart {
+                instance = Some(start());
+            }
+            Ok(())
+        })
+    })
+}
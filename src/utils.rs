@@ -1,5 +1,7 @@
 use std::env;
+use std::fs::create_dir_all;
 use std::path::{Path, PathBuf};
+use crate::error::Error;
 
 // https://stackoverflow.com/questions/37498864/finding-executable-in-path-with-rust/37499032#37499032
 pub(crate) fn find_in_path<P>(name: P) -> Option<PathBuf> where P: AsRef<Path> {
@@ -15,4 +17,75 @@ pub(crate) fn find_in_path<P>(name: P) -> Option<PathBuf> where P: AsRef<Path> {
             })
             .next()
     })
-}
\ No newline at end of file
+}
+
+/// Return the directory used by OSImage to store state (signed binaries, variable stores etc.) of the specified
+/// workspace and create it, if it doesn't exist.
+pub(crate) fn state_directory<P: AsRef<Path>>(workspace_path: P) -> Result<PathBuf, Error> {
+    let path = workspace_path.as_ref().join("target/osimage");
+    if !path.exists() {
+        create_dir_all(&path)?;
+    }
+    Ok(path)
+}
+
+/// Parse an address from the command line, either as hexadecimal number with `0x` prefix or as decimal number
+pub(crate) fn parse_address(value: &str) -> Result<u64, String> {
+    match value.strip_prefix("0x").or(value.strip_prefix("0X")) {
+        Some(value) => u64::from_str_radix(value, 16),
+        None => value.parse()
+    }.map_err(|error| error.to_string())
+}
+
+/// Parse a size with an optional binary unit suffix (e.g. `512K`, `64M` or `1G`) into bytes
+pub(crate) fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let (number, unit) = value.find(|character: char| !character.is_ascii_digit())
+        .map_or((value, ""), |index| value.split_at(index));
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().trim_end_matches("IB").trim_end_matches('B') {
+        "" => 1,
+        "K" => 1024,
+        "M" => 1024 * 1024,
+        "G" => 1024 * 1024 * 1024,
+        _ => return Err(format!("Invalid size '{}'", value))
+    };
+    number.parse::<u64>().ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .filter(|size| *size > 0)
+        .ok_or(format!("Invalid size '{}'", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::{parse_address, parse_size};
+
+    #[test]
+    fn parse_sizes() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("512K"), Ok(512 * 1024));
+        assert_eq!(parse_size("64M"), Ok(64 * 1024 * 1024));
+        assert_eq!(parse_size(" 2g "), Ok(2 * 1024 * 1024 * 1024));
+        assert_eq!(parse_size("4KB"), Ok(4096));
+        assert_eq!(parse_size("4KiB"), Ok(4096));
+        assert_eq!(parse_size("16 MiB"), Ok(16 * 1024 * 1024));
+        assert_eq!(parse_size("100B"), Ok(100));
+    }
+
+    #[test]
+    fn parse_invalid_sizes() {
+        for size in ["", "0", "0M", "M", "1.5M", "-1M", "1T", "1 K M", "99999999999G"] {
+            assert_eq!(parse_size(size), Err(format!("Invalid size '{}'", size.trim())), "{}", size);
+        }
+    }
+
+    #[test]
+    fn parse_addresses() {
+        assert_eq!(parse_address("0x1000"), Ok(0x1000));
+        assert_eq!(parse_address("0XFFFF800000000000"), Ok(0xFFFF800000000000));
+        assert_eq!(parse_address("4096"), Ok(4096));
+        assert!(parse_address("0x").is_err());
+        assert!(parse_address("1000h").is_err());
+        assert!(parse_address("0x1_0000_0000_0000_0000").is_err());
+        assert!(parse_address("0x10000000000000000").is_err());
+    }
+}
@@ -1,5 +1,9 @@
+use std::fmt::{Display, Formatter};
+use std::fs;
 use std::path::Path;
 use cargo_toml::Manifest;
+use colorful::{Color, Colorful};
+use log::{debug, info};
 use toml::Value;
 use crate::error::Error;
 
@@ -14,4 +18,311 @@ pub(crate) fn find_manifest_and_validate<P: AsRef<Path>>(path: P) -> Result<Mani
     // Get manifest and validate
     Manifest::from_path(&path).map_err(|_| Error::InvalidCargoFile(path.to_string_lossy()
         .into_owned()))
-}
\ No newline at end of file
+}
+
+/// The boot protocol the kernel is loaded with, specified by `boot_protocol` in the OSImage metadata of the kernel
+#[derive(Clone, Copy, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum BootProtocol {
+    Multiboot,
+    Multiboot2,
+    Limine
+}
+
+impl Display for BootProtocol {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(formatter, "{}", match self {
+            BootProtocol::Multiboot => "Multiboot",
+            BootProtocol::Multiboot2 => "Multiboot2",
+            BootProtocol::Limine => "Limine"
+        })
+    }
+}
+
+impl BootProtocol {
+    pub(crate) fn from_name(name: &str) -> Option<BootProtocol> {
+        match name {
+            "multiboot" => Some(BootProtocol::Multiboot),
+            "multiboot2" => Some(BootProtocol::Multiboot2),
+            "limine" => Some(BootProtocol::Limine),
+            _ => None
+        }
+    }
+}
+
+const MULTIBOOT_MAGIC: u32 = 0x1BADB002;
+const MULTIBOOT_SEARCH_LENGTH: usize = 8192;
+const MULTIBOOT2_MAGIC: u32 = 0xE85250D6;
+const MULTIBOOT2_SEARCH_LENGTH: usize = 32768;
+const LIMINE_COMMON_MAGIC: [u64; 2] = [0xC7B1DD30DF4C8B88, 0x0A82E883A194F07B];
+const LIMINE_BASE_REVISION_MAGIC: [u64; 2] = [0xF9562B2D5C95A6C8, 0x6A7B384944536BDC];
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4).map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    data.get(offset..offset + 8).map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+/// Validate the Multiboot header in the first 8 KiB of the kernel (4-byte aligned, magic + flags + checksum = 0)
+fn validate_multiboot(data: &[u8]) -> Result<(), String> {
+    let not_found = || String::from("Magic 0x1BADB002 not found in the first 8192 bytes");
+    let search_length = data.len().min(MULTIBOOT_SEARCH_LENGTH);
+    if search_length < 12 {
+        return Err(not_found());
+    }
+
+    let offset = (0..=search_length - 12).step_by(4)
+        .find(|offset| read_u32(data, *offset) == Some(MULTIBOOT_MAGIC))
+        .ok_or_else(not_found)?;
+
+    let flags = read_u32(data, offset + 4).unwrap();
+    let checksum = read_u32(data, offset + 8).unwrap();
+    if MULTIBOOT_MAGIC.wrapping_add(flags).wrapping_add(checksum) != 0 {
+        return Err(format!("Checksum {:#x} of header at offset {:#x} is invalid", checksum, offset));
+    }
+    debug!("Found Multiboot header at offset {:#x} with flags {:#x}", offset, flags);
+    Ok(())
+}
+
+/// Validate the Multiboot2 header in the first 32 KiB of the kernel (8-byte aligned, magic + architecture + length +
+/// checksum = 0) and the tags of the header, which must be terminated by the end tag
+fn validate_multiboot2(data: &[u8]) -> Result<(), String> {
+    let not_found = || String::from("Magic 0xE85250D6 not found in the first 32768 bytes");
+    let search_length = data.len().min(MULTIBOOT2_SEARCH_LENGTH);
+    if search_length < 16 {
+        return Err(not_found());
+    }
+
+    let offset = (0..=search_length - 16).step_by(8)
+        .find(|offset| read_u32(data, *offset) == Some(MULTIBOOT2_MAGIC))
+        .ok_or_else(not_found)?;
+
+    let architecture = read_u32(data, offset + 4).unwrap();
+    let header_length = read_u32(data, offset + 8).unwrap() as usize;
+    let checksum = read_u32(data, offset + 12).unwrap();
+    if MULTIBOOT2_MAGIC.wrapping_add(architecture).wrapping_add(header_length as u32).wrapping_add(checksum) != 0 {
+        return Err(format!("Checksum {:#x} of header at offset {:#x} is invalid", checksum, offset));
+    }
+
+    if architecture != 0 && architecture != 4 {
+        return Err(format!("Architecture {} of header at offset {:#x} is invalid", architecture, offset));
+    }
+
+    // Walk tags (8-byte aligned) until the end tag
+    let header_end = offset + header_length;
+    let mut tag_offset = offset + 16;
+    while tag_offset + 8 <= header_end {
+        let tag_type = data.get(tag_offset..tag_offset + 2).map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+            .ok_or(format!("Header at offset {:#x} exceeds the file", offset))?;
+        let tag_size = read_u32(data, tag_offset + 4).unwrap_or_default() as usize;
+        if tag_type == 0 && tag_size == 8 {
+            debug!("Found Multiboot2 header at offset {:#x} with length {:#x}", offset, header_length);
+            return Ok(());
+        }
+
+        if tag_size < 8 {
+            return Err(format!("Tag {} at offset {:#x} has invalid size {}", tag_type, tag_offset, tag_size));
+        }
+        tag_offset += (tag_size + 7) & !7;
+    }
+    Err(format!("Header at offset {:#x} has no end tag within its length {:#x}", offset, header_length))
+}
+
+/// Validate the Limine requests of the kernel. At least one request or the base revision tag must be present.
+fn validate_limine(data: &[u8]) -> Result<(), String> {
+    let mut requests = 0;
+    let mut base_revision = None;
+    let last_offset = data.len().checked_sub(24);
+    for offset in last_offset.into_iter().flat_map(|last_offset| (0..=last_offset).step_by(8)) {
+        let magic = [read_u64(data, offset).unwrap(), read_u64(data, offset + 8).unwrap()];
+        if magic == LIMINE_COMMON_MAGIC {
+            requests += 1;
+        } else if magic == LIMINE_BASE_REVISION_MAGIC {
+            base_revision = read_u64(data, offset + 16);
+        }
+    }
+
+    if requests == 0 && base_revision.is_none() {
+        return Err(String::from("No Limine requests or base revision tag found"));
+    }
+    debug!("Found {} Limine requests (base revision {:?})", requests, base_revision);
+    Ok(())
+}
+
+/// Validate the header or requests of the boot protocol in the specified kernel ELF file
+pub(crate) fn validate_boot_protocol<P: AsRef<Path>>(kernel: P, protocol: BootProtocol) -> Result<(), Error> {
+    let kernel_name = kernel.as_ref().to_string_lossy().into_owned();
+    let data = fs::read(&kernel)?;
+    if !data.starts_with(b"\x7FELF") {
+        return Err(Error::InvalidExecutable(kernel_name));
+    }
+
+    match protocol {
+        BootProtocol::Multiboot => validate_multiboot(&data),
+        BootProtocol::Multiboot2 => validate_multiboot2(&data),
+        BootProtocol::Limine => validate_limine(&data)
+    }.map_err(|message| Error::InvalidBootProtocol(kernel_name.clone(), protocol.to_string(), message))?;
+    info!("Validated {} header of kernel {}", protocol, kernel_name.gradient(Color::Cyan));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::error::Error;
+    use crate::validate::{BootProtocol, LIMINE_BASE_REVISION_MAGIC, LIMINE_COMMON_MAGIC, MULTIBOOT2_MAGIC,
+                          MULTIBOOT_MAGIC, validate_boot_protocol, validate_limine, validate_multiboot,
+                          validate_multiboot2};
+
+    fn kernel(length: usize) -> Vec<u8> {
+        let mut data = vec![0; length];
+        data[..4].copy_from_slice(b"\x7FELF");
+        data
+    }
+
+    fn write_u32(data: &mut [u8], offset: usize, value: u32) {
+        data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn write_u64(data: &mut [u8], offset: usize, value: u64) {
+        data[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn multiboot_header(data: &mut [u8], offset: usize, flags: u32) {
+        write_u32(data, offset, MULTIBOOT_MAGIC);
+        write_u32(data, offset + 4, flags);
+        write_u32(data, offset + 8, 0u32.wrapping_sub(MULTIBOOT_MAGIC).wrapping_sub(flags));
+    }
+
+    /// Write the Multiboot2 header with an information request tag and the end tag
+    fn multiboot2_header(data: &mut [u8], offset: usize, architecture: u32) {
+        let length = 16 + 16 + 8;
+        write_u32(data, offset, MULTIBOOT2_MAGIC);
+        write_u32(data, offset + 4, architecture);
+        write_u32(data, offset + 8, length);
+        write_u32(data, offset + 12, 0u32.wrapping_sub(MULTIBOOT2_MAGIC).wrapping_sub(architecture)
+            .wrapping_sub(length));
+        write_u32(data, offset + 16, 1);
+        write_u32(data, offset + 20, 12);
+        write_u32(data, offset + 24, 6);
+        write_u32(data, offset + 32, 0);
+        write_u32(data, offset + 36, 8);
+    }
+
+    #[test]
+    fn multiboot_header_is_valid() {
+        let mut data = kernel(4096);
+        multiboot_header(&mut data, 0x1000 - 64, 0x3);
+        assert_eq!(validate_multiboot(&data), Ok(()));
+    }
+
+    #[test]
+    fn multiboot_header_at_last_offset_is_found() {
+        let mut data = kernel(8192 + 64);
+        multiboot_header(&mut data, 8192 - 12, 0x3);
+        assert_eq!(validate_multiboot(&data), Ok(()));
+
+        let mut data = kernel(8192 + 64);
+        multiboot_header(&mut data, 8192 - 8, 0x3);
+        assert!(validate_multiboot(&data).unwrap_err().contains("not found"));
+    }
+
+    #[test]
+    fn multiboot_checksum_is_checked() {
+        let mut data = kernel(4096);
+        multiboot_header(&mut data, 64, 0x3);
+        write_u32(&mut data, 72, 0x1234);
+        assert_eq!(validate_multiboot(&data), Err(String::from("Checksum 0x1234 of header at offset 0x40 is invalid")));
+    }
+
+    #[test]
+    fn multiboot_rejects_short_kernel() {
+        assert!(validate_multiboot(&kernel(8)).unwrap_err().contains("not found"));
+    }
+
+    #[test]
+    fn multiboot2_header_is_valid() {
+        let mut data = kernel(4096);
+        multiboot2_header(&mut data, 128, 0);
+        assert_eq!(validate_multiboot2(&data), Ok(()));
+
+        let mut data = kernel(32768 + 64);
+        multiboot2_header(&mut data, 32768, 4);
+        assert!(validate_multiboot2(&data).unwrap_err().contains("not found"));
+    }
+
+    #[test]
+    fn multiboot2_header_at_last_offset_is_found() {
+        let mut data = kernel(64);
+        write_u32(&mut data, 48, MULTIBOOT2_MAGIC);
+        write_u32(&mut data, 56, 24);
+        write_u32(&mut data, 60, 0u32.wrapping_sub(MULTIBOOT2_MAGIC).wrapping_sub(24));
+        assert_eq!(validate_multiboot2(&data), Err(String::from("Header at offset 0x30 exceeds the file")));
+    }
+
+    #[test]
+    fn multiboot2_checksum_and_architecture_are_checked() {
+        let mut data = kernel(4096);
+        multiboot2_header(&mut data, 128, 0);
+        write_u32(&mut data, 140, 0);
+        assert_eq!(validate_multiboot2(&data), Err(String::from("Checksum 0x0 of header at offset 0x80 is invalid")));
+
+        let mut data = kernel(4096);
+        multiboot2_header(&mut data, 128, 2);
+        assert_eq!(validate_multiboot2(&data),
+            Err(String::from("Architecture 2 of header at offset 0x80 is invalid")));
+    }
+
+    #[test]
+    fn multiboot2_end_tag_is_required() {
+        let mut data = kernel(4096);
+        multiboot2_header(&mut data, 128, 0);
+        write_u32(&mut data, 160, 5);
+        assert_eq!(validate_multiboot2(&data),
+            Err(String::from("Header at offset 0x80 has no end tag within its length 0x28")));
+    }
+
+    #[test]
+    fn limine_requests_are_found() {
+        let mut data = kernel(4096);
+        write_u64(&mut data, 256, LIMINE_COMMON_MAGIC[0]);
+        write_u64(&mut data, 264, LIMINE_COMMON_MAGIC[1]);
+        assert_eq!(validate_limine(&data), Ok(()));
+
+        let mut data = kernel(4096);
+        write_u64(&mut data, 4096 - 24, LIMINE_BASE_REVISION_MAGIC[0]);
+        write_u64(&mut data, 4096 - 16, LIMINE_BASE_REVISION_MAGIC[1]);
+        write_u64(&mut data, 4096 - 8, 1);
+        assert_eq!(validate_limine(&data), Ok(()));
+    }
+
+    #[test]
+    fn limine_requires_requests() {
+        let mut data = kernel(4096);
+        write_u64(&mut data, 256, LIMINE_COMMON_MAGIC[0]);
+        assert_eq!(validate_limine(&data), Err(String::from("No Limine requests or base revision tag found")));
+        assert!(validate_limine(&kernel(16)).is_err());
+    }
+
+    #[test]
+    fn kernel_must_be_elf() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("kernel");
+        let mut data = kernel(4096);
+        multiboot_header(&mut data, 64, 0);
+        fs::write(&path, &data).unwrap();
+        validate_boot_protocol(&path, BootProtocol::Multiboot).unwrap();
+        assert!(matches!(validate_boot_protocol(&path, BootProtocol::Limine), Err(Error::InvalidBootProtocol(..))));
+
+        data[0] = 0;
+        fs::write(&path, &data).unwrap();
+        assert!(matches!(validate_boot_protocol(&path, BootProtocol::Multiboot), Err(Error::InvalidExecutable(_))));
+    }
+
+    #[test]
+    fn boot_protocol_names() {
+        assert_eq!(BootProtocol::from_name("multiboot2"), Some(BootProtocol::Multiboot2));
+        assert_eq!(BootProtocol::from_name("Limine"), None);
+    }
+}